@@ -0,0 +1,1132 @@
+use serenity::client::Context;
+use serenity::framework::standard::{macros::*, Args, CommandResult};
+use serenity::model::channel::{Channel, Message};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::prelude::Mentionable;
+use serenity::utils::Colour;
+
+use crate::exemption::Exemption;
+use crate::modlog::DeletionFilter;
+use crate::notice::NoticeConfig;
+use crate::penalty::{EscalationStep, Penalty};
+use crate::policy::{parse_domain, AttachmentType, DomainFilter, Rule, ThreadMode};
+use crate::settings::{self, category_of, Scope, Settings};
+use crate::status;
+
+/// The longest prefix a guild can choose
+const MAX_PREFIX_LENGTH: usize = 10;
+
+#[group]
+#[commands(toggle_channel, toggle_category)]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_MESSAGES)]
+struct General;
+
+#[group]
+#[prefixes("shut")]
+#[commands(
+    policy,
+    attachments,
+    domains,
+    caption,
+    notice,
+    dm,
+    log,
+    logchannel,
+    prefix,
+    escalation,
+    exempt,
+    status,
+    list
+)]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_MESSAGES)]
+struct Shut;
+
+/// Start or stop removing non-media messages from a channel, or every channel in a category
+///
+/// Without `enable` or `disable` the channel is toggled. The channel defaults to the current one.
+#[command]
+#[usage("[enable|disable] [#channel]")]
+async fn toggle_channel(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+    let usage = usage(ctx, guild_id, "toggle_channel [enable|disable] [#channel]").await;
+
+    let enforced = match args.current().and_then(parse_switch) {
+        Some(enforced) => {
+            args.advance();
+            Some(enforced)
+        }
+        None => None,
+    };
+    // Pick the channel to change, which can be a category
+    let (channel, is_category) = match args.current() {
+        None => (msg.channel_id, false),
+        Some(arg) => {
+            let channel = match arg.parse::<ChannelId>() {
+                Ok(channel) => channel.to_channel(ctx).await.ok(),
+                Err(_) => None,
+            };
+            match channel {
+                Some(Channel::Guild(channel)) if channel.guild_id == guild_id => {
+                    (channel.id, false)
+                }
+                Some(Channel::Category(category)) if category.guild_id == guild_id => {
+                    (category.id, true)
+                }
+                _ => {
+                    msg.reply(ctx, usage).await?;
+                    return Ok(());
+                }
+            }
+        }
+    };
+    if args.advance().remaining() > 0 {
+        msg.reply(ctx, usage).await?;
+        return Ok(());
+    }
+
+    let settings_lock = settings::get(ctx).await;
+
+    let mut settings = settings_lock.write().await;
+    let response = if is_category {
+        let enforced =
+            enforced.unwrap_or_else(|| !settings.is_category_enforced(guild_id, channel));
+        let was_enforced = settings.set_category_enforced(guild_id, channel, enforced)?;
+        enforce_category_response(channel, was_enforced, enforced)
+    } else {
+        let category = category_of(&ctx.cache, channel);
+        let enforced =
+            enforced.unwrap_or_else(|| !settings.is_enforced(guild_id, channel, category));
+        let was_enforced = settings.set_channel_enforced(guild_id, channel, category, enforced)?;
+        enforce_response(channel, was_enforced, enforced)
+    };
+    drop(settings);
+
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// Start or stop removing non-media messages from every channel in the current category
+#[command]
+async fn toggle_category(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+    let category = match category_of(&ctx.cache, msg.channel_id) {
+        Some(category) => category,
+        None => {
+            msg.reply(ctx, "This channel isn't in a category").await?;
+            return Ok(());
+        }
+    };
+
+    let settings_lock = settings::get(ctx).await;
+
+    let category_was_enforced = settings_lock
+        .write()
+        .await
+        .toggle_category(guild_id, category)?;
+
+    msg.reply(
+        ctx,
+        toggle_category_response(category, category_was_enforced),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Show the channel's policy, or change one rule with `~shut policy <rule> <on|off>`
+///
+/// `~shut policy threads <enforce|ignore|starter>` chooses how the policy applies to threads.
+#[command]
+#[usage("[<rule> <on|off> | threads <enforce|ignore|starter>]")]
+async fn policy(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let channel = msg.channel(&ctx).await?.guild().ok_or("Not in guild")?;
+
+    let settings_lock = settings::get(ctx).await;
+
+    if matches!(args.current(), Some(arg) if arg.eq_ignore_ascii_case("threads")) {
+        args.advance();
+        let mode = match args.single::<String>() {
+            Ok(mode) => mode.parse::<ThreadMode>(),
+            Err(_) => Err("Expected `enforce`, `ignore` or `starter` after `threads`".to_string()),
+        };
+        let mode = match mode {
+            Ok(mode) => mode,
+            Err(why) => {
+                msg.reply(ctx, why).await?;
+                return Ok(());
+            }
+        };
+
+        let mut settings = settings_lock.write().await;
+        let mut policy = settings.policy(
+            channel.guild_id,
+            msg.channel_id,
+            category_of(&ctx.cache, channel.id),
+        );
+        policy.threads = mode;
+        settings.set_policy(channel.guild_id, msg.channel_id, policy)?;
+    } else if !args.is_empty() {
+        let rule = match args.single::<String>()?.parse::<Rule>() {
+            Ok(rule) => rule,
+            Err(why) => {
+                msg.reply(ctx, why).await?;
+                return Ok(());
+            }
+        };
+        let value = match args
+            .single::<String>()
+            .ok()
+            .as_deref()
+            .and_then(parse_switch)
+        {
+            Some(value) => value,
+            None => {
+                msg.reply(ctx, "Expected `on` or `off` after the rule name")
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let mut settings = settings_lock.write().await;
+        let mut policy = settings.policy(
+            channel.guild_id,
+            msg.channel_id,
+            category_of(&ctx.cache, channel.id),
+        );
+        policy.set(rule, value);
+        settings.set_policy(channel.guild_id, msg.channel_id, policy)?;
+    }
+
+    let category = category_of(&ctx.cache, channel.id);
+    let response = policy_summary(
+        &*settings_lock.read().await,
+        channel.guild_id,
+        channel.id,
+        category,
+    );
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// Show or set the longest caption allowed next to media in the channel
+#[command]
+#[usage("[<characters>|off]")]
+async fn caption(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let channel = msg.channel(&ctx).await?.guild().ok_or("Not in guild")?;
+
+    let settings_lock = settings::get(ctx).await;
+
+    if !args.is_empty() {
+        let arg = args.single::<String>()?;
+        let limit = if parse_switch(&arg) == Some(false) {
+            None
+        } else {
+            match arg.parse::<u32>() {
+                Ok(limit) => Some(limit),
+                Err(_) => {
+                    let usage =
+                        usage(ctx, channel.guild_id, "shut caption [<characters>|off]").await;
+                    msg.reply(ctx, usage).await?;
+                    return Ok(());
+                }
+            }
+        };
+
+        let mut settings = settings_lock.write().await;
+        let mut policy = settings.policy(
+            channel.guild_id,
+            msg.channel_id,
+            category_of(&ctx.cache, channel.id),
+        );
+        policy.max_caption = limit;
+        settings.set_policy(channel.guild_id, msg.channel_id, policy)?;
+    }
+
+    let category = category_of(&ctx.cache, channel.id);
+    let response = caption_summary(
+        &*settings_lock.read().await,
+        channel.guild_id,
+        channel.id,
+        category,
+    );
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// Show or edit which attachment types count as media in the channel
+#[command]
+#[usage("[add|remove <type>... | clear]")]
+async fn attachments(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let channel = msg.channel(&ctx).await?.guild().ok_or("Not in guild")?;
+
+    let settings_lock = settings::get(ctx).await;
+
+    if !args.is_empty() {
+        let action = args.single::<String>()?.to_ascii_lowercase();
+        let mut types = Vec::new();
+        for arg in args.iter::<String>() {
+            match arg?.parse::<AttachmentType>() {
+                Ok(ty) => types.push(ty),
+                Err(why) => {
+                    msg.reply(ctx, why).await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut settings = settings_lock.write().await;
+        match action.as_str() {
+            "add" if !types.is_empty() => {
+                for ty in types {
+                    settings.add_attachment_type(channel.guild_id, msg.channel_id, ty)?;
+                }
+            }
+            "remove" if !types.is_empty() => {
+                for ty in types {
+                    settings.remove_attachment_type(channel.guild_id, msg.channel_id, &ty)?;
+                }
+            }
+            "clear" => settings.clear_attachment_types(channel.guild_id, msg.channel_id)?,
+            _ => {
+                drop(settings);
+                let usage = usage(
+                    ctx,
+                    channel.guild_id,
+                    "shut attachments [add|remove <type>... | clear]",
+                )
+                .await;
+                msg.reply(
+                    ctx,
+                    format!(
+                        "{}, where a type is `image`, `video`, `audio`, a MIME type like \
+                        `image/png` or an extension like `.png`",
+                        usage
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let category = category_of(&ctx.cache, channel.id);
+    let response = attachments_summary(
+        &*settings_lock.read().await,
+        channel.guild_id,
+        channel.id,
+        category,
+    );
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// Show or edit the domains that count as media, for the channel or the whole server
+#[command]
+#[usage("[server] [allow|deny|remove <domain>...]")]
+async fn domains(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let channel = msg.channel(&ctx).await?.guild().ok_or("Not in guild")?;
+    let channel_scope = Scope::Channel(channel.guild_id, channel.id);
+    let guild_scope = Scope::Guild(channel.guild_id);
+
+    let settings_lock = settings::get(ctx).await;
+
+    let scope = match args.current() {
+        Some(arg) if matches!(arg.to_ascii_lowercase().as_str(), "server" | "guild") => {
+            args.advance();
+            guild_scope
+        }
+        _ => channel_scope,
+    };
+
+    if !args.is_empty() {
+        let action = args.single::<String>()?.to_ascii_lowercase();
+        let mut domains = Vec::new();
+        for arg in args.iter::<String>() {
+            let arg = arg?;
+            match parse_domain(&arg) {
+                Some(domain) => domains.push(domain),
+                None => {
+                    msg.reply(ctx, format!("`{}` is not a valid domain", arg))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut settings = settings_lock.write().await;
+        match action.as_str() {
+            "allow" | "deny" if !domains.is_empty() => {
+                for domain in domains {
+                    settings.add_domain(scope, domain, action == "allow")?;
+                }
+            }
+            "remove" if !domains.is_empty() => {
+                for domain in domains {
+                    settings.remove_domain(scope, &domain)?;
+                }
+            }
+            _ => {
+                drop(settings);
+                let usage = usage(
+                    ctx,
+                    channel.guild_id,
+                    "shut domains [server] [allow|deny|remove <domain>...]",
+                )
+                .await;
+                msg.reply(ctx, usage).await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let response = domains_summary(&*settings_lock.read().await, channel.guild_id, channel.id);
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// Show or edit the notice posted when a message is removed, for the server or one channel
+#[command]
+#[usage("[channel] [template <text> | lifetime <seconds|forever|off> | embed <on|off> | window <seconds|off> | cooldown <seconds|off> | reset]")]
+async fn notice(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let channel = msg.channel(&ctx).await?.guild().ok_or("Not in guild")?;
+
+    let settings_lock = settings::get(ctx).await;
+
+    let scope = match args.current().map(str::to_ascii_lowercase).as_deref() {
+        Some("channel") => {
+            args.advance();
+            Scope::Channel(channel.guild_id, channel.id)
+        }
+        Some("server" | "guild") => {
+            args.advance();
+            Scope::Guild(channel.guild_id)
+        }
+        _ => Scope::Guild(channel.guild_id),
+    };
+
+    if !args.is_empty() {
+        let action = args.single::<String>()?.to_ascii_lowercase();
+        let rest = args.rest().trim();
+
+        let mut settings = settings_lock.write().await;
+        let mut config = settings.notice_config(scope);
+        let valid = match action.as_str() {
+            "template" if !rest.is_empty() => {
+                config.template = rest.to_string();
+                true
+            }
+            "lifetime" => match rest.to_ascii_lowercase().as_str() {
+                "forever" | "never" => {
+                    config.enabled = true;
+                    config.lifetime = None;
+                    true
+                }
+                "off" | "none" => {
+                    config.enabled = false;
+                    true
+                }
+                seconds => match seconds.parse() {
+                    Ok(seconds) => {
+                        config.enabled = true;
+                        config.lifetime = Some(seconds);
+                        true
+                    }
+                    Err(_) => false,
+                },
+            },
+            "embed" => match parse_switch(rest) {
+                Some(embed) => {
+                    config.embed = embed;
+                    true
+                }
+                None => false,
+            },
+            "window" => match parse_seconds(rest) {
+                Some(seconds) => {
+                    config.coalesce_window = seconds;
+                    true
+                }
+                None => false,
+            },
+            "cooldown" => match parse_seconds(rest) {
+                Some(seconds) => {
+                    config.cooldown = seconds;
+                    true
+                }
+                None => false,
+            },
+            "reset" => {
+                settings.reset_notice_config(scope)?;
+                true
+            }
+            _ => false,
+        };
+
+        if !valid {
+            drop(settings);
+            let usage = usage(
+                ctx,
+                channel.guild_id,
+                "shut notice [channel] [template <text> | lifetime <seconds|forever|off> \
+                | embed <on|off> | window <seconds|off> | cooldown <seconds|off> | reset]",
+            )
+            .await;
+            msg.reply(
+                ctx,
+                format!(
+                    "{}\nTemplates can use {}",
+                    usage,
+                    NoticeConfig::PLACEHOLDERS.join(", ")
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+        if action != "reset" {
+            settings.set_notice_config(scope, config)?;
+        }
+    }
+
+    let response = notice_summary(&*settings_lock.read().await, scope);
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// Show or set whether authors get a DM with their removed message instead of a notice
+#[command]
+#[usage("[on|off]")]
+async fn dm(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+
+    let settings_lock = settings::get(ctx).await;
+
+    if !args.is_empty() {
+        let value = match parse_switch(&args.single::<String>()?) {
+            Some(value) => value,
+            None => {
+                msg.reply(ctx, usage(ctx, guild_id, "shut dm [on|off]").await)
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let mut settings = settings_lock.write().await;
+        let mut config = settings.guild_config(guild_id);
+        config.dm_offenders = value;
+        settings.set_guild_config(guild_id, config)?;
+    }
+
+    let dm_offenders = settings_lock
+        .read()
+        .await
+        .guild_config(guild_id)
+        .dm_offenders;
+    let response = if dm_offenders {
+        "Authors get a DM with their removed message, or a notice in the channel if their DMs are closed"
+    } else {
+        "Authors get a notice in the channel when their message is removed"
+    };
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// List recently removed messages, optionally only from one member or channel
+#[command]
+#[usage("[@user|#channel]")]
+async fn log(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+
+    let filter = match args.current() {
+        None => DeletionFilter::All,
+        Some(arg) => {
+            let is_channel = arg.starts_with("<#")
+                || matches!(arg.parse::<ChannelId>(), Ok(channel) if ctx.cache.guild_channel(channel).is_some());
+            match (is_channel, arg.parse::<ChannelId>(), arg.parse::<UserId>()) {
+                (true, Ok(channel), _) => DeletionFilter::Channel(channel),
+                (false, _, Ok(user)) => DeletionFilter::User(user),
+                _ => {
+                    msg.reply(ctx, usage(ctx, guild_id, "shut log [@user|#channel]").await)
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    let settings_lock = settings::get(ctx).await;
+
+    let deletions = settings_lock.read().await.deletions(guild_id, filter, 10)?;
+    let response = if deletions.is_empty() {
+        "No removed messages found".to_string()
+    } else {
+        let lines: Vec<_> = deletions
+            .iter()
+            .map(|deletion| deletion.summary())
+            .collect();
+        lines.join("\n")
+    };
+    // Don't ping everyone in the log
+    msg.channel_id
+        .send_message(ctx, |m| {
+            m.content(response)
+                .reference_message(msg)
+                .allowed_mentions(|am| am.empty_parse())
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Show or set the channel removed messages are logged to
+#[command]
+#[usage("[#channel|off]")]
+async fn logchannel(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+
+    let settings_lock = settings::get(ctx).await;
+
+    if !args.is_empty() {
+        let arg = args.single::<String>()?;
+        let log_channel = if parse_switch(&arg) == Some(false) {
+            None
+        } else {
+            match arg.parse::<ChannelId>() {
+                Ok(channel)
+                    if ctx.cache.guild_channel(channel).map(|c| c.guild_id) == Some(guild_id) =>
+                {
+                    Some(channel)
+                }
+                _ => {
+                    let usage = usage(ctx, guild_id, "shut logchannel [#channel|off]").await;
+                    msg.reply(ctx, usage).await?;
+                    return Ok(());
+                }
+            }
+        };
+
+        let mut settings = settings_lock.write().await;
+        let mut config = settings.guild_config(guild_id);
+        config.log_channel = log_channel;
+        settings.set_guild_config(guild_id, config)?;
+    }
+
+    let log_channel = settings_lock
+        .read()
+        .await
+        .guild_config(guild_id)
+        .log_channel;
+    let response = match log_channel {
+        Some(channel) => format!("Removed messages are logged to {}", channel.mention()),
+        None => "Removed messages aren't logged to a channel".to_string(),
+    };
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// Show or set the prefix for commands in this server
+#[command]
+#[usage("[<prefix>]")]
+async fn prefix(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+
+    let settings_lock = settings::get(ctx).await;
+
+    if !args.is_empty() {
+        let prefix = args.single::<String>()?;
+        // Mentions are already a prefix, and a long prefix is only a typo waiting to happen
+        if !args.is_empty()
+            || prefix.chars().count() > MAX_PREFIX_LENGTH
+            || prefix.starts_with("<@")
+        {
+            let response = format!(
+                "{}, where the prefix has no spaces and at most {} characters",
+                usage(ctx, guild_id, "shut prefix [<prefix>]").await,
+                MAX_PREFIX_LENGTH
+            );
+            msg.reply(ctx, response).await?;
+            return Ok(());
+        }
+
+        let mut settings = settings_lock.write().await;
+        let mut config = settings.guild_config(guild_id);
+        config.prefix = prefix;
+        settings.set_guild_config(guild_id, config)?;
+    }
+
+    let prefix = settings_lock.read().await.guild_config(guild_id).prefix;
+    let response = format!(
+        "Commands in this server start with `{}`, or with a mention of SHUT",
+        prefix
+    );
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+/// Show or edit the penalties for members who keep breaking the rules
+#[command]
+#[usage("[window <hours> | add <count> <warn|timeout <minutes>|removerole <@role>|kick> | remove <count>]")]
+async fn escalation(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+    let usage = format!(
+        "{}, where a penalty is `warn`, `timeout <minutes>`, `removerole <@role>` or `kick`",
+        usage(
+            ctx,
+            guild_id,
+            "shut escalation [window <hours> | add <count> <penalty> | remove <count>]"
+        )
+        .await
+    );
+
+    let settings_lock = settings::get(ctx).await;
+
+    if !args.is_empty() {
+        let action = args.single::<String>()?.to_ascii_lowercase();
+        let count = args.single::<u32>().ok().filter(|count| *count > 0);
+        let rest: Vec<String> = args.iter::<String>().filter_map(Result::ok).collect();
+
+        let mut settings = settings_lock.write().await;
+        match (action.as_str(), count) {
+            ("window", Some(hours)) if rest.is_empty() => {
+                let mut config = settings.guild_config(guild_id);
+                config.escalation_window = hours as u64 * 60 * 60;
+                settings.set_guild_config(guild_id, config)?;
+            }
+            ("add", Some(violations)) => match Penalty::parse(&rest) {
+                Ok(penalty) => settings.set_escalation_step(
+                    guild_id,
+                    EscalationStep {
+                        violations,
+                        penalty,
+                    },
+                )?,
+                Err(why) => {
+                    drop(settings);
+                    msg.reply(ctx, why).await?;
+                    return Ok(());
+                }
+            },
+            ("remove", Some(violations)) if rest.is_empty() => {
+                settings.remove_escalation_step(guild_id, violations)?;
+            }
+            _ => {
+                drop(settings);
+                msg.reply(ctx, usage).await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let response = escalation_summary(&*settings_lock.read().await, guild_id);
+    msg.channel_id
+        .send_message(ctx, |m| {
+            m.content(response)
+                .reference_message(msg)
+                .allowed_mentions(|am| am.empty_parse())
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Show or edit who is exempt from enforcement, for the server or one channel
+#[command]
+#[usage("[channel] [add|remove <@role|@user|permission>...]")]
+async fn exempt(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let channel = msg.channel(&ctx).await?.guild().ok_or("Not in guild")?;
+    let usage = usage(
+        ctx,
+        channel.guild_id,
+        "shut exempt [channel] [add|remove <@role|@user|permission>...]",
+    )
+    .await;
+
+    let settings_lock = settings::get(ctx).await;
+
+    let scope = match args.current().map(str::to_ascii_lowercase).as_deref() {
+        Some("channel") => {
+            args.advance();
+            Scope::Channel(channel.guild_id, channel.id)
+        }
+        Some("server" | "guild") => {
+            args.advance();
+            Scope::Guild(channel.guild_id)
+        }
+        _ => Scope::Guild(channel.guild_id),
+    };
+
+    if !args.is_empty() {
+        let action = args.single::<String>()?.to_ascii_lowercase();
+        let mut exemptions = Vec::new();
+        for arg in args.iter::<String>() {
+            match Exemption::parse(&ctx.cache, channel.guild_id, &arg?) {
+                Ok(exemption) => exemptions.push(exemption),
+                Err(why) => {
+                    msg.reply(ctx, why).await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut settings = settings_lock.write().await;
+        match action.as_str() {
+            "add" if !exemptions.is_empty() => {
+                for exemption in exemptions {
+                    settings.add_exemption(scope, exemption)?;
+                }
+            }
+            "remove" if !exemptions.is_empty() => {
+                for exemption in exemptions {
+                    settings.remove_exemption(scope, exemption)?;
+                }
+            }
+            _ => {
+                drop(settings);
+                msg.reply(ctx, usage).await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let response = exemptions_summary(&*settings_lock.read().await, channel.guild_id, channel.id);
+    // Don't ping the exempt roles and users
+    msg.channel_id
+        .send_message(ctx, |m| {
+            m.content(response)
+                .reference_message(msg)
+                .allowed_mentions(|am| am.empty_parse())
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub fn toggle_response(channel: ChannelId, was_banned: bool) -> String {
+    if was_banned {
+        format!(
+            "SHUT will stop removing messages from {}",
+            channel.mention()
+        )
+    } else {
+        format!(
+            "SHUT will now remove non-media messages from {}",
+            channel.mention()
+        )
+    }
+}
+
+/// Show whether a channel is enforced and why, with its rules
+#[command]
+#[usage("[#channel]")]
+async fn status(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+    let channel = match args.current() {
+        None => msg.channel_id,
+        Some(arg) => {
+            // Don't show settings from other servers
+            let in_guild = |channel: ChannelId| {
+                ctx.cache.guild_channel(channel).map(|c| c.guild_id) == Some(guild_id)
+                    || ctx.cache.category(channel).map(|c| c.guild_id) == Some(guild_id)
+            };
+            match arg.parse::<ChannelId>() {
+                Ok(channel) if in_guild(channel) => channel,
+                _ => {
+                    msg.reply(ctx, usage(ctx, guild_id, "shut status [#channel]").await)
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+    };
+    let category = category_of(&ctx.cache, channel);
+
+    let settings_lock = settings::get(ctx).await;
+
+    let (reason, summary, rules) = {
+        let settings = settings_lock.read().await;
+        (
+            status::enforcement_reason(&settings, guild_id, channel, category),
+            policy_summary(&settings, guild_id, channel, category),
+            settings.channel_rules(guild_id, channel, category),
+        )
+    };
+    msg.channel_id
+        .send_message(ctx, |m| {
+            m.embed(|e| {
+                e.title("SHUT status")
+                    .description(reason)
+                    .field("Policy", summary, false)
+                    .field("In plain words", rules.describe(), false)
+                    .colour(Colour::BLURPLE)
+            })
+            .reference_message(msg)
+            .allowed_mentions(|am| am.empty_parse())
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// List every enforced channel in the server with its rules
+#[command]
+#[usage("[page]")]
+async fn list(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Not in guild")?;
+    let page = args.single::<usize>().unwrap_or(1).saturating_sub(1);
+
+    let settings_lock = settings::get(ctx).await;
+
+    let (lines, prefix) = {
+        let settings = settings_lock.read().await;
+        (
+            status::enforced_channels(&ctx.cache, &settings, guild_id),
+            settings.guild_config(guild_id).prefix,
+        )
+    };
+    let pages = status::page_count(&lines);
+    let page = page.min(pages - 1);
+    msg.channel_id
+        .send_message(ctx, |m| {
+            m.embed(|e| status::list_embed(e, &lines, page, &prefix))
+                .components(|c| status::list_buttons(c, page, pages))
+                .reference_message(msg)
+                .allowed_mentions(|am| am.empty_parse())
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Reply for enabling or disabling a channel, which may not have changed anything.
+pub fn enforce_response(channel: ChannelId, was_enforced: bool, enforced: bool) -> String {
+    match (was_enforced, enforced) {
+        (true, true) => format!(
+            "SHUT is already removing non-media messages from {}",
+            channel.mention()
+        ),
+        (false, false) => format!(
+            "SHUT already isn't removing messages from {}",
+            channel.mention()
+        ),
+        _ => toggle_response(channel, was_enforced),
+    }
+}
+
+pub fn enforce_category_response(
+    category: ChannelId,
+    was_enforced: bool,
+    enforced: bool,
+) -> String {
+    match (was_enforced, enforced) {
+        (true, true) => format!(
+            "SHUT is already removing non-media messages from every channel in {}",
+            category.mention()
+        ),
+        (false, false) => format!(
+            "SHUT already isn't enforcing {} as a whole",
+            category.mention()
+        ),
+        _ => toggle_category_response(category, was_enforced),
+    }
+}
+
+pub fn toggle_category_response(category: ChannelId, was_enforced: bool) -> String {
+    if was_enforced {
+        format!(
+            "SHUT will stop removing messages from the channels in {}, \
+            except those toggled on by themselves",
+            category.mention()
+        )
+    } else {
+        format!(
+            "SHUT will now remove non-media messages from every channel in {}",
+            category.mention()
+        )
+    }
+}
+
+pub fn policy_summary(
+    settings: &Settings,
+    guild: GuildId,
+    channel: ChannelId,
+    category: Option<ChannelId>,
+) -> String {
+    let enforced = settings.is_enforced(guild, channel, category)
+        || settings.is_category_enforced(guild, channel);
+    let policy = settings.policy(guild, channel, category);
+
+    let mut response = format!(
+        "Policy for {} ({}):\n",
+        channel.mention(),
+        if enforced { "enforced" } else { "not enforced" }
+    );
+    match category {
+        Some(category) if !settings.has_policy_override(guild, channel) => {
+            response.push_str(&format!(
+                "Same as {}, changing a rule gives this channel its own policy\n",
+                category.mention()
+            ))
+        }
+        _ => {}
+    }
+    for rule in Rule::ALL {
+        response.push_str(&format!(
+            "`{}`: {}\n",
+            rule,
+            if policy.get(rule) { "on" } else { "off" }
+        ));
+    }
+    match policy.max_caption {
+        Some(limit) => response.push_str(&format!("`caption`: {} characters\n", limit)),
+        None => response.push_str("`caption`: unlimited\n"),
+    }
+    response.push_str(&format!("`threads`: {}\n", policy.threads));
+    response
+}
+
+pub fn caption_summary(
+    settings: &Settings,
+    guild: GuildId,
+    channel: ChannelId,
+    category: Option<ChannelId>,
+) -> String {
+    match settings.policy(guild, channel, category).max_caption {
+        Some(limit) => format!(
+            "Media posts in {} may have up to {} characters of text, not counting links",
+            channel.mention(),
+            limit
+        ),
+        None => format!(
+            "Media posts in {} may have any amount of text",
+            channel.mention()
+        ),
+    }
+}
+
+pub fn attachments_summary(
+    settings: &Settings,
+    guild: GuildId,
+    channel: ChannelId,
+    category: Option<ChannelId>,
+) -> String {
+    let types = settings.attachment_types(guild, channel, category);
+    if types.is_empty() {
+        format!("Any attachment counts as media in {}", channel.mention())
+    } else {
+        let types: Vec<_> = types.iter().map(|ty| format!("`{}`", ty)).collect();
+        format!(
+            "Attachments count as media in {} if they are: {}",
+            channel.mention(),
+            types.join(", ")
+        )
+    }
+}
+
+pub fn domains_summary(settings: &Settings, guild: GuildId, channel: ChannelId) -> String {
+    let guild_domains = settings.domain_filter(Scope::Guild(guild));
+    let channel_domains = settings.domain_filter(Scope::Channel(guild, channel));
+
+    let format_list = |domains: &[String]| {
+        if domains.is_empty() {
+            "none".to_string()
+        } else {
+            let domains: Vec<_> = domains.iter().map(|d| format!("`{}`", d)).collect();
+            domains.join(", ")
+        }
+    };
+    let format_filter = |filter: &DomainFilter| {
+        format!(
+            "allowed: {}\ndenied: {}",
+            format_list(&filter.allow),
+            format_list(&filter.deny)
+        )
+    };
+    format!(
+        "**Server**\n{}\n**{}**\n{}\n\n\
+        Links count as media unless their domain is denied. \
+        If there is an allow-list, only those domains count; \
+        the channel's allow-list replaces the server's.",
+        format_filter(&guild_domains),
+        channel.mention(),
+        format_filter(&channel_domains),
+    )
+}
+
+pub fn notice_summary(settings: &Settings, scope: Scope) -> String {
+    let config = settings.notice_config(scope);
+    let heading = match scope {
+        Scope::Guild(_) => "Notice for this server".to_string(),
+        Scope::Channel(_, channel) if settings.has_notice_override(scope) => {
+            format!("Notice for {}", channel.mention())
+        }
+        Scope::Channel(_, channel) => {
+            format!("Notice for {} (same as the server)", channel.mention())
+        }
+    };
+    format!("**{}**\n{}", heading, config.describe())
+}
+
+pub fn escalation_summary(settings: &Settings, guild: GuildId) -> String {
+    let window = settings.guild_config(guild).escalation_window / (60 * 60);
+    let steps = settings.escalation_steps(guild);
+
+    let mut response = format!("Counting removed messages from the last {} hours\n", window);
+    if steps.is_empty() {
+        response.push_str("No penalties are set up");
+    }
+    for step in steps {
+        response.push_str(&format!("{} messages: {}\n", step.violations, step.penalty));
+    }
+    response
+}
+
+pub fn exemptions_summary(settings: &Settings, guild: GuildId, channel: ChannelId) -> String {
+    let format_list = |exemptions: &[Exemption]| {
+        if exemptions.is_empty() {
+            "nobody".to_string()
+        } else {
+            let exemptions: Vec<_> = exemptions.iter().map(ToString::to_string).collect();
+            exemptions.join(", ")
+        }
+    };
+    format!(
+        "**Exempt in the whole server**\n{}\n**Exempt in {}**\n{}",
+        format_list(settings.exemptions(Scope::Guild(guild))),
+        channel.mention(),
+        format_list(settings.exemptions(Scope::Channel(guild, channel))),
+    )
+}
+
+/// How to use a command, with the prefix of the guild it's used in.
+async fn usage(ctx: &Context, guild: GuildId, command: &str) -> String {
+    let settings_lock = settings::get(ctx).await;
+    let prefix = settings_lock.read().await.guild_config(guild).prefix;
+    format!("Usage: `{}{}`", prefix, command)
+}
+
+/// Parse an on/off style argument
+fn parse_switch(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" | "enable" => Some(true),
+        "off" | "false" | "no" | "disable" => Some(false),
+        _ => None,
+    }
+}
+
+/// A number of seconds, where `off` is 0.
+fn parse_seconds(value: &str) -> Option<u64> {
+    match parse_switch(value) {
+        Some(false) => Some(0),
+        _ => value.parse().ok(),
+    }
+}
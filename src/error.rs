@@ -0,0 +1,156 @@
+use serenity::client::Context;
+use serenity::http::error::Error as HttpError;
+use serenity::model::error::Error as ModelError;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::prelude::Mentionable;
+use tracing::{error, warn};
+
+use std::error::Error as StdError;
+use std::io;
+
+use crate::metrics;
+use crate::settings;
+
+/// Discord's JSON error codes for a missing permission and for a channel the bot can't see
+const MISSING_PERMISSIONS: isize = 50013;
+const MISSING_ACCESS: isize = 50001;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("database error: {0}")]
+    Database(#[from] sqlite::Error),
+    #[error("Discord error: {0}")]
+    Discord(#[source] Box<serenity::Error>),
+    #[error("could not create the data directory: {0}")]
+    Io(#[from] io::Error),
+    #[error("migration {version} ({name}) failed: {source}")]
+    Migration {
+        version: i64,
+        name: &'static str,
+        source: sqlite::Error,
+    },
+    #[error(
+        "the database is at schema version {version} but this build only knows up to version \
+        {supported}, run a newer build or restore a backup"
+    )]
+    SchemaTooNew { version: i64, supported: i64 },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Boxed, serenity's errors are large and would make every `Result` carry them
+impl From<serenity::Error> for Error {
+    fn from(error: serenity::Error) -> Self {
+        Error::Discord(Box::new(error))
+    }
+}
+
+impl Error {
+    /// Whether Discord refused because the bot lacks a permission, which a moderator can fix.
+    pub fn is_missing_permissions(&self) -> bool {
+        matches!(self, Error::Discord(error) if is_missing_permissions(error))
+    }
+
+    /// What to tell the member whose command failed.
+    pub fn user_message(&self) -> &'static str {
+        user_message(self.is_missing_permissions())
+    }
+}
+
+/// What to tell the member whose prefix command failed, for the boxed errors commands return.
+pub fn command_message(error: &(dyn StdError + Send + Sync + 'static)) -> &'static str {
+    let missing_permissions = match error.downcast_ref::<Error>() {
+        Some(error) => error.is_missing_permissions(),
+        None => {
+            matches!(error.downcast_ref::<serenity::Error>(), Some(error) if is_missing_permissions(error))
+        }
+    };
+    user_message(missing_permissions)
+}
+
+/// Whether a boxed command error came from a request to Discord.
+pub fn is_discord_error(error: &(dyn StdError + Send + Sync + 'static)) -> bool {
+    matches!(error.downcast_ref::<Error>(), Some(Error::Discord(_)))
+        || error.is::<serenity::Error>()
+}
+
+fn user_message(missing_permissions: bool) -> &'static str {
+    if missing_permissions {
+        "SHUT is missing a permission it needs for this, ask a server admin to check its role"
+    } else {
+        "Something went wrong, the error was logged"
+    }
+}
+
+pub fn is_missing_permissions(error: &serenity::Error) -> bool {
+    match error {
+        serenity::Error::Model(ModelError::InvalidPermissions(_)) => true,
+        serenity::Error::Http(error) => matches!(
+            error.as_ref(),
+            HttpError::UnsuccessfulRequest(response)
+                if matches!(response.error.code, MISSING_PERMISSIONS | MISSING_ACCESS)
+        ),
+        _ => false,
+    }
+}
+
+/// Whether Discord rejected a request itself, so that sending it again won't help.
+pub fn is_client_error(error: &serenity::Error) -> bool {
+    match error {
+        serenity::Error::Http(error) => matches!(
+            error.as_ref(),
+            HttpError::UnsuccessfulRequest(response) if response.status_code.is_client_error()
+        ),
+        _ => false,
+    }
+}
+
+/// Log an error from handling an event in a guild, and tell the guild's moderators through
+/// its log channel when the bot is missing a permission.
+pub async fn report(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel: ChannelId,
+    action: &str,
+    error: &Error,
+) {
+    error!(
+        guild = %guild_id,
+        channel = %channel,
+        action,
+        error = %error,
+        "Could not {}", action
+    );
+    if let Error::Discord(_) = error {
+        metrics::get(ctx).await.discord_error();
+    }
+    if !error.is_missing_permissions() {
+        return;
+    }
+
+    let settings_lock = settings::get(ctx).await;
+    let log_channel = settings_lock
+        .read()
+        .await
+        .guild_config(guild_id)
+        .log_channel;
+
+    if let Some(log_channel) = log_channel {
+        let message = format!(
+            "SHUT could not {} in {} because it's missing a permission. \
+            It needs Manage Messages there, and Moderate Members, Kick Members or \
+            Ban Members for escalation penalties.",
+            action,
+            channel.mention()
+        );
+        if let Err(why) = log_channel.say(ctx, message).await {
+            metrics::get(ctx).await.discord_error();
+            warn!(
+                guild = %guild_id,
+                channel = %log_channel,
+                error = %why,
+                "Could not report a missing permission to the log channel"
+            );
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use serenity::cache::Cache;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, RoleId, UserId};
+use serenity::model::Permissions;
+use serenity::prelude::Mentionable;
+
+use std::fmt;
+
+/// Permissions that can be used to exempt members, by the name commands accept.
+const PERMISSIONS: [(&str, Permissions); 8] = [
+    ("administrator", Permissions::ADMINISTRATOR),
+    ("manage_guild", Permissions::MANAGE_GUILD),
+    ("manage_channels", Permissions::MANAGE_CHANNELS),
+    ("manage_messages", Permissions::MANAGE_MESSAGES),
+    ("manage_roles", Permissions::MANAGE_ROLES),
+    ("moderate_members", Permissions::MODERATE_MEMBERS),
+    ("kick_members", Permissions::KICK_MEMBERS),
+    ("ban_members", Permissions::BAN_MEMBERS),
+];
+
+/// Someone whose messages are never removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exemption {
+    Role(RoleId),
+    User(UserId),
+    /// Anyone with this permission anywhere in the guild
+    Permission(Permissions),
+}
+
+impl Exemption {
+    /// Parse a role or user mention, an id, or a permission name like `manage_messages`.
+    ///
+    /// A bare id is taken to be a role if the guild has a role with that id.
+    pub fn parse(cache: &Cache, guild: GuildId, arg: &str) -> Result<Self, String> {
+        let name = arg.to_ascii_lowercase().replace(' ', "_");
+        if let Some((_, permission)) = PERMISSIONS.iter().find(|(n, _)| *n == name) {
+            return Ok(Exemption::Permission(*permission));
+        }
+
+        if arg.starts_with("<@&") {
+            if let Ok(role) = arg.parse() {
+                return Ok(Exemption::Role(role));
+            }
+        } else if let Ok(user) = arg.parse::<UserId>() {
+            if !arg.starts_with("<@") && cache.role(guild, user.0).is_some() {
+                return Ok(Exemption::Role(RoleId(user.0)));
+            }
+            return Ok(Exemption::User(user));
+        }
+
+        let names: Vec<_> = PERMISSIONS.iter().map(|(name, _)| *name).collect();
+        Err(format!(
+            "`{}` is not a role, a user or one of these permissions: {}",
+            arg,
+            names.join(", ")
+        ))
+    }
+
+    /// The `kind` and `value` columns this exemption is stored as.
+    pub fn to_row(self) -> (&'static str, i64) {
+        match self {
+            Exemption::Role(role) => ("role", role.0 as i64),
+            Exemption::User(user) => ("user", user.0 as i64),
+            Exemption::Permission(permission) => ("permission", permission.bits() as i64),
+        }
+    }
+
+    pub fn from_row(kind: &str, value: i64) -> Option<Self> {
+        match kind {
+            "role" => Some(Exemption::Role(RoleId(value as u64))),
+            "user" => Some(Exemption::User(UserId(value as u64))),
+            "permission" => Permissions::from_bits(value as u64).map(Exemption::Permission),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Exemption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exemption::Role(role) => write!(f, "{}", role.mention()),
+            Exemption::User(user) => write!(f, "{}", user.mention()),
+            Exemption::Permission(permission) => {
+                match PERMISSIONS.iter().find(|(_, p)| p == permission) {
+                    Some((name, _)) => write!(f, "`{}`", name),
+                    None => write!(f, "`{}`", permission),
+                }
+            }
+        }
+    }
+}
+
+/// Whether the author of a message is covered by any of the exemptions.
+pub async fn is_exempt(
+    ctx: &Context,
+    guild_id: GuildId,
+    msg: &Message,
+    exemptions: &[Exemption],
+) -> bool {
+    if exemptions.contains(&Exemption::User(msg.author.id)) {
+        return true;
+    }
+    // Only look up the member when a role or permission could exempt them
+    if !exemptions
+        .iter()
+        .any(|exemption| !matches!(exemption, Exemption::User(_)))
+    {
+        return false;
+    }
+
+    let member = match guild_id.member(ctx, msg.author.id).await {
+        Ok(member) => member,
+        Err(_) => return false,
+    };
+    let permissions = member
+        .permissions(ctx)
+        .unwrap_or_else(|_| Permissions::empty());
+
+    exemptions.iter().any(|exemption| match exemption {
+        Exemption::Role(role) => member.roles.contains(role),
+        Exemption::User(_) => false,
+        Exemption::Permission(permission) => permissions.contains(*permission),
+    })
+}
@@ -0,0 +1,24 @@
+use tracing_subscriber::EnvFilter;
+
+use std::env;
+
+/// Used when `RUST_LOG` isn't set: everything from SHUT, and only warnings from its libraries
+const DEFAULT_FILTER: &str = "warn,shut2=info";
+
+/// Install the subscriber, configured by the environment.
+///
+/// `RUST_LOG` takes comma separated directives like `info` or `serenity::gateway=debug`.
+/// `LOG_FORMAT=json` writes one JSON object per line.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| DEFAULT_FILTER.into());
+    let json = env::var("LOG_FORMAT")
+        .map(|format| format.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
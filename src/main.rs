@@ -1,110 +1,224 @@
-use dotenv::dotenv;
+mod commands;
+mod error;
+mod exemption;
+mod logging;
+mod metrics;
+mod migrations;
+mod modlog;
+mod notice;
+mod penalty;
+mod policy;
+mod settings;
+mod sharding;
+mod shutdown;
+mod slash;
+mod status;
 
-use lazy_static::lazy_static;
-use regex::Regex;
+use dotenv::dotenv;
 
 use serenity::async_trait;
+use serenity::client::bridge::gateway::event::ShardStageUpdateEvent;
 use serenity::client::{Client, Context, EventHandler};
-use serenity::framework::standard::{macros::*, CommandResult};
+use serenity::framework::standard::macros::*;
+use serenity::framework::standard::CommandResult;
 use serenity::framework::StandardFramework;
-use serenity::model::channel::Message;
-use serenity::model::guild::Guild;
-use serenity::model::id::ChannelId;
-use serenity::model::{id::GuildId, prelude::Ready};
-use serenity::prelude::{GatewayIntents, Mentionable, RwLock, TypeMapKey};
-use sqlite::Value;
-
-use std::collections::HashSet;
+use serenity::http::Http;
+use serenity::model::channel::{
+    ChannelCategory, ChannelType, GuildChannel, Message, PartialGuildChannel,
+};
+use serenity::model::event::MessageUpdateEvent;
+use serenity::model::guild::{Guild, UnavailableGuild};
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::interactions::Interaction;
+use serenity::model::prelude::Ready;
+use serenity::prelude::{GatewayIntents, RwLock};
+use tracing::{error, info, info_span, warn, Instrument};
+
 use std::env;
 use std::error::Error;
-use std::fs;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use commands::{GENERAL_GROUP, SHUT_GROUP};
+use exemption::Exemption;
+use metrics::Metrics;
+use notice::NoticeState;
+use policy::{ChannelRules, ThreadMessage};
+use settings::{Scope, Settings};
+use sharding::{LoadedShards, Sharding};
+use shutdown::Tasks;
 
-struct Settings {
-    connection: Mutex<sqlite::Connection>,
+struct Handler;
+#[async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        let shard = ready.shard.map(|[id, _]| id);
+        info!(user = %ready.user.name, shard, "Connected to Discord");
 
-    banned_channels: HashSet<ChannelId>,
-}
+        // Every shard gets a ready, but commands only need registering once
+        if shard.unwrap_or(0) != 0 {
+            return;
+        }
+        // Comma separated guild ids to register slash commands in, instead of globally
+        let guilds: Vec<GuildId> = env::var("COMMAND_GUILDS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|id| id.trim().parse().ok().map(GuildId))
+            .collect();
+        if let Err(why) = slash::register(&ctx, &guilds).await {
+            error!(error = %why, "Could not register slash commands");
+        }
+    }
 
-impl Settings {
-    fn load() -> Self {
-        fs::create_dir_all("data").unwrap();
-
-        let mut banned_channels = HashSet::new();
-        let connection = sqlite::open("data/settings.sqlite").unwrap();
-
-        // Create schema if it doesn't exist
-        connection
-            .execute("CREATE TABLE IF NOT EXISTS banned_channels (channel_id INTEGER NOT NULL);")
-            .unwrap();
-
-        // Load banned_channels
-        {
-            let mut cursor = connection
-                .prepare("SELECT * FROM banned_channels")
-                .unwrap()
-                .into_cursor();
-
-            while let Some(row) = cursor.next().unwrap() {
-                if let Value::Integer(channel_id) = row[0] {
-                    banned_channels.insert(ChannelId(channel_id as u64));
-                }
+    async fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        info!(
+            shard = event.shard_id.0,
+            from = %event.old,
+            to = %event.new,
+            "Shard connection changed"
+        );
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let result = match interaction {
+            Interaction::ApplicationCommand(command) => slash::handle_command(&ctx, &command).await,
+            Interaction::Autocomplete(autocomplete) => {
+                slash::handle_autocomplete(&ctx, &autocomplete).await
+            }
+            Interaction::MessageComponent(component) => {
+                status::handle_page_button(&ctx, &component).await
             }
+            _ => Ok(()),
+        };
+        if let Err(why) = result {
+            metrics::get(&ctx).await.discord_error();
+            warn!(error = %why, "Could not respond to an interaction");
         }
+    }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        // Discord also sends updates when it adds link embeds, those aren't edits
+        if event.edited_timestamp.is_none() {
+            return;
+        }
+        let guild_id = match event.guild_id {
+            Some(guild_id) => guild_id,
+            None => return,
+        };
 
-        return Settings {
-            connection: Mutex::new(connection),
-            banned_channels,
+        let _task = Tasks::start(&ctx).await;
+        let enforcement = match enforcement(&ctx, guild_id, event.channel_id).await {
+            Some(enforcement) if enforcement.rules.policy.edits => enforcement,
+            _ => return,
         };
+
+        // The edited message is only cached if it was sent recently
+        let mut msg = match new {
+            Some(msg) => msg,
+            None => match event.channel_id.message(&ctx, event.id).await {
+                Ok(msg) => msg,
+                Err(_) => return,
+            },
+        };
+        if msg.author.bot {
+            return;
+        }
+        msg.guild_id = Some(guild_id);
+
+        let span = info_span!(
+            "message_update",
+            guild = %guild_id,
+            channel = %msg.channel_id,
+            user = %msg.author.id,
+            message_id = %msg.id
+        );
+        let result = enforce(&ctx, guild_id, &msg, enforcement)
+            .instrument(span.clone())
+            .await;
+        if let Err(why) = result {
+            error::report(
+                &ctx,
+                guild_id,
+                msg.channel_id,
+                "check an edited message",
+                &why,
+            )
+            .instrument(span)
+            .await;
+        }
     }
 
-    fn toggle_channel(&mut self, channel: ChannelId) -> bool {
-        let conn_lock = self.connection.lock().unwrap();
-        if self.banned_channels.remove(&channel) {
-            let mut statement = conn_lock
-                .prepare("DELETE FROM banned_channels WHERE channel_id = ?")
-                .unwrap();
-            statement.bind(1, channel.0 as i64).unwrap();
-            statement.next().unwrap();
+    async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: bool) {
+        let settings_lock = settings::get(&ctx).await;
 
-            return true;
-        } else {
-            let mut statement = conn_lock
-                .prepare("INSERT INTO banned_channels VALUES (?)")
-                .unwrap();
-            statement.bind(1, channel.0 as i64).unwrap();
-            statement.next().unwrap();
-
-            self.banned_channels.insert(channel);
-            return false;
+        let channels = channel_ids(&guild);
+        let result = settings_lock.write().await.load_guild(guild.id, &channels);
+        if let Err(why) = result {
+            error!(guild = %guild.id, error = %why, "Could not load the guild settings");
         }
     }
-}
 
-impl TypeMapKey for Settings {
-    type Value = Arc<RwLock<Settings>>;
-}
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, _full: Option<Guild>) {
+        let settings_lock = settings::get(&ctx).await;
+
+        let mut settings = settings_lock.write().await;
+        // An outage also deletes the guild, its settings come back with the next guild_create
+        if incomplete.unavailable {
+            settings.unload_guild(incomplete.id);
+        } else if let Err(why) = settings.delete_guild(incomplete.id) {
+            error!(guild = %incomplete.id, error = %why, "Could not delete the guild settings");
+        }
+    }
 
-#[group]
-#[commands(toggle_channel)]
-#[only_in(guilds)]
-#[required_permissions(MANAGE_MESSAGES)]
-struct General;
+    async fn channel_delete(&self, ctx: Context, channel: &GuildChannel) {
+        forget_channel(&ctx, channel.guild_id, channel.id).await;
+    }
 
-struct Handler;
-#[async_trait]
-impl EventHandler for Handler {
-    async fn ready(&self, _ctx: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
+    async fn category_delete(&self, ctx: Context, category: &ChannelCategory) {
+        forget_channel(&ctx, category.guild_id, category.id).await;
+    }
+
+    async fn thread_delete(&self, ctx: Context, thread: PartialGuildChannel) {
+        forget_channel(&ctx, thread.guild_id, thread.id).await;
     }
 
     async fn cache_ready(&self, ctx: Context, guilds: Vec<GuildId>) {
-        println!("Cache built successfully!");
-        println!("Guilds:");
-        for guildid in guilds {
-            let guild = Guild::get(&ctx.http, guildid).await.unwrap();
-            println!("\t{}", guild.name)
+        info!(guilds = guilds.len(), "Cache built successfully");
+        for guildid in &guilds {
+            let name = guildid.name(&ctx.cache).unwrap_or_default();
+            info!(guild = %guildid, name = %name, "Serving guild");
+        }
+
+        let loaded_shards = {
+            let data = ctx.data.read().await;
+            data.get::<LoadedShards>()
+                .expect("Expected LoadedShards in TypeMap.")
+                .clone()
+        };
+        if !loaded_shards.loaded(ctx.shard_id, ctx.cache.shard_count()) {
+            return;
+        }
+
+        // Every guild the bot is in is known, unclaimed settings are from guilds it left
+        let guilds: Vec<_> = guilds
+            .into_iter()
+            .filter_map(|guild| Some((guild, ctx.cache.guild_field(guild, channel_ids)?)))
+            .collect();
+        let settings_lock = settings::get(&ctx).await;
+        let result = settings_lock.read().await.delete_unclaimed(&guilds);
+        match result {
+            Ok(0) => {}
+            Ok(deleted) => info!(
+                deleted,
+                "Deleted the settings of channels in guilds the bot left"
+            ),
+            Err(why) => error!(error = %why, "Could not delete the settings no guild claimed"),
         }
     }
 }
@@ -112,16 +226,29 @@ impl EventHandler for Handler {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
+    logging::init();
+
+    let settings = Arc::new(RwLock::new(Settings::load()?));
 
-    let settings = Arc::new(RwLock::new(Settings::load()));
+    let sharding = Sharding::from_env()?;
+
+    // Login with a bot token from the environment
+    let token = env::var("DISCORD_TOKEN").map_err(|_| "DISCORD_TOKEN is not set")?;
+    // Mentioning the bot always works, in case a guild forgets its prefix
+    let bot_id = Http::new(&token).get_current_user().await?.id;
 
     let framework = StandardFramework::new()
-        .configure(|c| c.with_whitespace(true).prefix('~'))
+        .configure(|c| {
+            c.with_whitespace(true)
+                .prefix("")
+                .dynamic_prefix(prefix)
+                .on_mention(Some(bot_id))
+        })
         .normal_message(normal_message)
-        .group(&GENERAL_GROUP);
+        .after(after)
+        .group(&GENERAL_GROUP)
+        .group(&SHUT_GROUP);
 
-    // Login with a bot token from the environment
-    let token = env::var("DISCORD_TOKEN").expect("token");
     let mut client = Client::builder(
         token,
         GatewayIntents::GUILDS
@@ -132,19 +259,63 @@ async fn main() -> Result<(), Box<dyn Error>> {
     )
     .framework(framework)
     .event_handler(Handler)
-    .await
-    .expect("Error creating client");
+    .await?;
 
+    let metrics = Arc::new(Metrics::default());
+    let tasks = Arc::new(Tasks::default());
     {
         let mut data = client.data.write().await;
-        data.insert::<Settings>(settings);
+        data.insert::<Settings>(settings.clone());
+        data.insert::<Metrics>(metrics.clone());
+        data.insert::<NoticeState>(Arc::new(NoticeState::default()));
+        data.insert::<Tasks>(tasks.clone());
+        data.insert::<LoadedShards>(Arc::new(LoadedShards::new(sharding)));
+    }
+
+    // Including the notices that were due while the bot was offline, once their guild is loaded
+    tokio::spawn(notice::run_removals(
+        client.cache_and_http.http.clone(),
+        settings.clone(),
+        metrics.clone(),
+    ));
+
+    // Serve metrics and the health check when a port is configured
+    if let Ok(port) = env::var("METRICS_PORT") {
+        let port = port
+            .trim()
+            .parse()
+            .map_err(|_| format!("METRICS_PORT is not a port: {}", port))?;
+        // Only reachable from this host unless METRICS_ADDR says otherwise, the metrics name guilds
+        let ip = match env::var("METRICS_ADDR") {
+            Ok(ip) => ip
+                .trim()
+                .parse()
+                .map_err(|_| format!("METRICS_ADDR is not an IP address: {}", ip))?,
+            Err(_) => IpAddr::from(Ipv4Addr::LOCALHOST),
+        };
+        let shards = client.shard_manager.lock().await.runners.clone();
+        tokio::spawn(metrics::serve(
+            SocketAddr::new(ip, port),
+            metrics.clone(),
+            shards,
+        ));
     }
 
-    // start listening for events by starting a single shard
-    if let Err(why) = client.start().await {
-        println!("An error occurred while running the client: {:?}", why);
+    // SIGTERM and SIGINT stop the shards, which makes the client return
+    tokio::spawn(shutdown::stop_on_signal(client.shard_manager.clone()));
+
+    info!(sharding = %sharding, "Starting shards");
+    if let Err(why) = sharding.start(&mut client).await {
+        error!(error = %why, "An error occurred while running the client");
     }
 
+    // Messages still being enforced can post a notice and queue it for removal
+    tasks.wait().await;
+    // Nothing is left to remove the queued notices, remove them early instead
+    notice::remove_notices(&client.cache_and_http.http, &settings, &metrics, i64::MAX).await;
+    settings.read().await.close()?;
+    info!("Shut down");
+
     Ok(())
 }
 
@@ -155,87 +326,273 @@ async fn normal_message(ctx: &Context, msg: &Message) {
         return;
     }
 
-    // Hyperlink regex
-    lazy_static! {
-        static ref LINK_RE: Regex = Regex::new(r#"https?://(www\.)?[-a-zA-Z0-9@:%._\+~#=]{1,256}\.[a-zA-Z0-9()]{1,6}\b([-a-zA-Z0-9()@:%_\+.~#?&/=]*)"#).unwrap();
-    }
-    let link = LINK_RE.is_match(&msg.content);
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
 
-    // More than 1 attachment or link
-    if link || msg.attachments.len() > 0 {
-        return;
+    let _task = Tasks::start(ctx).await;
+    let span = info_span!(
+        "normal_message",
+        guild = %guild_id,
+        channel = %msg.channel_id,
+        user = %msg.author.id,
+        message_id = %msg.id
+    );
+    async {
+        if let Some(enforcement) = enforcement(ctx, guild_id, msg.channel_id).await {
+            if let Err(why) = enforce(ctx, guild_id, msg, enforcement).await {
+                error::report(
+                    ctx,
+                    guild_id,
+                    msg.channel_id,
+                    "enforce the channel rules",
+                    &why,
+                )
+                .await;
+            }
+        }
     }
+    .instrument(span)
+    .await
+}
 
-    // Acquire data lock
-    let settings_lock = {
-        let data = ctx.data.read().await;
-        data.get::<Settings>()
-            .expect("Expected Settings in TypeMap.")
-            .clone()
+/// The command prefix of the guild a message was sent in.
+#[hook]
+async fn prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Some(settings::DEFAULT_PREFIX.to_string()),
     };
 
-    // Acquire settings lock + check if message is in banned channel
-    let in_banned_channel = {
-        let settings = settings_lock.read().await;
-        settings.banned_channels.contains(&msg.channel_id)
-    };
+    let settings_lock = settings::get(ctx).await;
+    let prefix = settings_lock.read().await.guild_config(guild_id).prefix;
+    Some(prefix)
+}
 
-    // Message needs to be in banned channel to delete it
-    if !in_banned_channel {
-        return;
+#[hook]
+async fn after(ctx: &Context, msg: &Message, command_name: &str, result: CommandResult) {
+    let why = match result {
+        Ok(()) => return,
+        Err(why) => why,
+    };
+    if error::is_discord_error(why.as_ref()) {
+        metrics::get(ctx).await.discord_error();
+    }
+    error!(
+        guild = ?msg.guild_id,
+        channel = %msg.channel_id,
+        user = %msg.author.id,
+        command = command_name,
+        error = %why,
+        "Command failed"
+    );
+
+    if let Err(why) = msg.reply(ctx, error::command_message(why.as_ref())).await {
+        metrics::get(ctx).await.discord_error();
+        warn!(
+            channel = %msg.channel_id,
+            user = %msg.author.id,
+            command = command_name,
+            error = %why,
+            "Could not report a failed command"
+        );
     }
+}
 
-    // Delete the message
-    msg.delete(&ctx).await.unwrap();
+/// The ids of a guild's channels, categories and threads.
+fn channel_ids(guild: &Guild) -> Vec<ChannelId> {
+    guild
+        .channels
+        .keys()
+        .copied()
+        .chain(guild.threads.iter().map(|thread| thread.id))
+        .collect()
+}
 
-    let reply_msg = msg
-        .channel_id
-        .say(&ctx, format!("{} SHUT!", msg.author.mention()))
-        .await
-        .unwrap();
+/// Delete the settings of a channel, category or thread that no longer exists.
+async fn forget_channel(ctx: &Context, guild_id: GuildId, channel: ChannelId) {
+    let settings_lock = settings::get(ctx).await;
 
-    tokio::time::sleep(Duration::from_secs(3)).await;
+    let result = settings_lock
+        .write()
+        .await
+        .delete_channel(guild_id, channel);
+    if let Err(why) = result {
+        error!(
+            guild = %guild_id,
+            channel = %channel,
+            error = %why,
+            "Could not delete the channel settings"
+        );
+    }
+}
 
-    reply_msg.delete(&ctx).await.unwrap();
+/// The rules that apply where a message was posted.
+struct Enforcement {
+    /// The channel whose settings apply, which is the parent for a thread that isn't enforced itself
+    channel: ChannelId,
+    /// The thread the message was posted in, if any
+    thread: Option<ChannelId>,
+    rules: ChannelRules,
+    exemptions: Vec<Exemption>,
 }
 
-#[command]
-async fn toggle_channel(ctx: &Context, msg: &Message) -> CommandResult {
-    let channel = msg.channel(&ctx).await?.guild().ok_or("Not in guild")?;
+/// The rules for messages in a channel, or `None` if the channel isn't enforced.
+async fn enforcement(ctx: &Context, guild_id: GuildId, channel: ChannelId) -> Option<Enforcement> {
+    let settings_lock = settings::get(ctx).await;
 
-    // Acquire data lock
-    let settings_lock = {
-        let data = ctx.data.read().await;
-        data.get::<Settings>()
-            .expect("Expected Settings in TypeMap.")
-            .clone()
-    };
+    // Threads and forum posts follow their parent channel, unless they're enforced themselves
+    let parent = thread_parent(ctx, guild_id, channel).await;
+    let thread = parent.map(|_| channel);
 
     // Acquire settings lock + check if message is in banned channel
-    let channel_was_banned = {
-        let mut settings = settings_lock.write().await;
-        settings.toggle_channel(msg.channel_id)
+    let settings = settings_lock.read().await;
+    let channel = match parent {
+        Some(parent) if !settings.is_channel_toggled(guild_id, channel) => parent,
+        _ => channel,
+    };
+    // Channels in an enforced category are enforced unless they opted out
+    let category = settings::category_of(&ctx.cache, channel);
+    if !settings.is_enforced(guild_id, channel, category) {
+        return None;
+    }
+    Some(Enforcement {
+        channel,
+        thread,
+        rules: settings.channel_rules(guild_id, channel, category),
+        exemptions: settings.channel_exemptions(guild_id, channel),
+    })
+}
+
+/// Check a message and remove it if it breaks the rules.
+async fn enforce(
+    ctx: &Context,
+    guild_id: GuildId,
+    msg: &Message,
+    enforcement: Enforcement,
+) -> error::Result<()> {
+    let Enforcement {
+        channel,
+        thread,
+        rules,
+        exemptions,
+    } = enforcement;
+
+    let thread = match thread {
+        Some(thread) if rules.tells_starter_apart() => {
+            Some(thread_message(ctx, thread, msg).await?)
+        }
+        Some(_) => Some(ThreadMessage::Reply),
+        None => None,
     };
 
-    if channel_was_banned {
-        msg.reply(
-            ctx,
-            format!(
-                "SHUT will stop removing messages from {}",
-                channel.mention()
-            ),
+    let metrics = metrics::get(ctx).await;
+    metrics.message_evaluated();
+    let violation = match rules.check(msg, thread) {
+        Ok(()) => return Ok(()),
+        Err(violation) => violation,
+    };
+
+    // Moderators and trusted members can post anything
+    if exemption::is_exempt(ctx, guild_id, msg, &exemptions).await {
+        return Ok(());
+    }
+
+    // Delete the message
+    msg.delete(&ctx).await?;
+    metrics.message_deleted(guild_id, violation.rule());
+    info!(
+        rule = violation.rule(),
+        action = "delete",
+        reason = %violation,
+        "Removed a message"
+    );
+
+    let settings_lock = settings::get(ctx).await;
+
+    let (notice_config, guild_config) = {
+        let settings = settings_lock.read().await;
+        (
+            settings.notice_config(Scope::Channel(guild_id, channel)),
+            settings.guild_config(guild_id),
         )
-        .await?;
-    } else {
-        msg.reply(
-            ctx,
-            format!(
-                "SHUT will now remove non-media messages from {}",
-                channel.mention()
-            ),
+    };
+
+    let deletion = modlog::Deletion::new(guild_id, msg, &violation);
+    let step = {
+        let settings = settings_lock.read().await;
+        settings.record_deletion(&deletion)?;
+
+        let since = deletion.deleted_at - guild_config.escalation_window as i64;
+        let count = settings.count_violations(guild_id, msg.author.id, since)?;
+        penalty::step_for(settings.escalation_steps(guild_id), count)
+    };
+    if let Some(log_channel) = guild_config.log_channel {
+        modlog::post_deletion(ctx, log_channel, msg, &deletion).await;
+    }
+    if let Some(step) = step {
+        penalty::apply(ctx, guild_id, msg, step, guild_config.log_channel).await;
+    }
+
+    // Fall back to the channel notice when the author has DMs closed
+    if guild_config.dm_offenders && notice::dm_offender(ctx, msg, &violation, &rules).await {
+        metrics.notice_sent();
+        return Ok(());
+    }
+    notice::send_notice(ctx, guild_id, msg, &violation, &notice_config).await
+}
+
+/// The channel a thread or forum post was created in, or `None` if the channel isn't a thread.
+async fn thread_parent(ctx: &Context, guild_id: GuildId, channel: ChannelId) -> Option<ChannelId> {
+    let is_thread = |kind| {
+        matches!(
+            kind,
+            ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread
         )
-        .await?;
+    };
+
+    if let Some(channel) = ctx.cache.guild_channel(channel) {
+        return if is_thread(channel.kind) {
+            channel.parent_id
+        } else {
+            None
+        };
+    }
+    // Only threads that were active when the bot joined are cached
+    let cached = ctx.cache.guild_field(guild_id, |guild| {
+        guild
+            .threads
+            .iter()
+            .find(|thread| thread.id == channel)
+            .map(|thread| thread.parent_id)
+    });
+    if let Some(parent) = cached.flatten() {
+        return parent;
     }
 
-    Ok(())
+    match channel.to_channel(ctx).await.ok()?.guild() {
+        Some(channel) if is_thread(channel.kind) => channel.parent_id,
+        _ => None,
+    }
+}
+
+/// Whether a message is the first one in its thread or forum post.
+async fn thread_message(
+    ctx: &Context,
+    thread: ChannelId,
+    msg: &Message,
+) -> serenity::Result<ThreadMessage> {
+    // A forum post shares its id with its first message
+    if msg.id.0 == thread.0 {
+        return Ok(ThreadMessage::Starter);
+    }
+    // Threads started from a message open with a system message pointing at it, so only the
+    // first message of a thread started on its own has nothing before it
+    let earlier = thread.messages(ctx, |r| r.before(msg.id).limit(1)).await?;
+    Ok(if earlier.is_empty() {
+        ThreadMessage::Starter
+    } else {
+        ThreadMessage::Reply
+    })
 }
@@ -0,0 +1,228 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serenity::client::bridge::gateway::{ShardId, ShardRunnerInfo};
+use serenity::client::Context;
+use serenity::gateway::ConnectionStage;
+use serenity::model::id::GuildId;
+use serenity::prelude::{Mutex, TypeMapKey};
+use tracing::{error, info};
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The shard runners of the shard manager, with their connection stage and latency
+pub type Shards = Arc<Mutex<HashMap<ShardId, ShardRunnerInfo>>>;
+
+/// Counters exposed on `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    messages_evaluated: AtomicU64,
+    /// Removed messages by guild and rule
+    messages_deleted: std::sync::Mutex<HashMap<(GuildId, &'static str), u64>>,
+    notices_sent: AtomicU64,
+    discord_errors: AtomicU64,
+}
+
+impl TypeMapKey for Metrics {
+    type Value = Arc<Metrics>;
+}
+
+impl Metrics {
+    /// A message in an enforced channel was checked against the channel's rules.
+    pub fn message_evaluated(&self) {
+        self.messages_evaluated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_deleted(&self, guild: GuildId, rule: &'static str) {
+        let mut deleted = self
+            .messages_deleted
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *deleted.entry((guild, rule)).or_default() += 1;
+    }
+
+    pub fn notice_sent(&self) {
+        self.notices_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A request to Discord failed.
+    pub fn discord_error(&self) {
+        self.discord_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters and the latency of each shard, in the Prometheus text format.
+    fn render(&self, shards: &HashMap<ShardId, ShardRunnerInfo>) -> String {
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "shut_messages_evaluated_total",
+            "Messages checked against the rules of an enforced channel.",
+        );
+        let _ = writeln!(
+            out,
+            "shut_messages_evaluated_total {}",
+            self.messages_evaluated.load(Ordering::Relaxed)
+        );
+
+        counter(
+            &mut out,
+            "shut_messages_deleted_total",
+            "Messages removed for breaking a rule.",
+        );
+        {
+            let deleted = self
+                .messages_deleted
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut deleted: Vec<_> = deleted.iter().collect();
+            deleted.sort();
+            for ((guild, rule), count) in deleted {
+                let _ = writeln!(
+                    out,
+                    "shut_messages_deleted_total{{guild=\"{}\",rule=\"{}\"}} {}",
+                    guild, rule, count
+                );
+            }
+        }
+
+        counter(
+            &mut out,
+            "shut_notices_sent_total",
+            "Notices posted or sent to the authors of removed messages.",
+        );
+        let _ = writeln!(
+            out,
+            "shut_notices_sent_total {}",
+            self.notices_sent.load(Ordering::Relaxed)
+        );
+
+        counter(
+            &mut out,
+            "shut_discord_errors_total",
+            "Requests to Discord that failed.",
+        );
+        let _ = writeln!(
+            out,
+            "shut_discord_errors_total {}",
+            self.discord_errors.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP shut_gateway_latency_seconds Time between a heartbeat and its acknowledgement."
+        );
+        let _ = writeln!(out, "# TYPE shut_gateway_latency_seconds gauge");
+        let mut shards: Vec<_> = shards.iter().collect();
+        shards.sort_by_key(|(id, _)| **id);
+        for (id, info) in shards {
+            if let Some(latency) = info.latency {
+                let _ = writeln!(
+                    out,
+                    "shut_gateway_latency_seconds{{shard=\"{}\"}} {}",
+                    id.0,
+                    latency.as_secs_f64()
+                );
+            }
+        }
+
+        out
+    }
+}
+
+/// One line per shard with its connection stage and latency.
+fn shard_status(shards: &HashMap<ShardId, ShardRunnerInfo>) -> String {
+    if shards.is_empty() {
+        return "no shards started\n".to_string();
+    }
+
+    let mut shards: Vec<_> = shards.iter().collect();
+    shards.sort_by_key(|(id, _)| **id);
+    let mut out = String::new();
+    for (id, info) in shards {
+        let _ = write!(out, "shard {}: {}", id.0, info.stage);
+        if let Some(latency) = info.latency {
+            let _ = write!(out, ", {} ms", latency.as_millis());
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn counter(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+}
+
+/// The metrics of the running bot.
+pub async fn get(ctx: &Context) -> Arc<Metrics> {
+    let data = ctx.data.read().await;
+    data.get::<Metrics>()
+        .expect("Expected Metrics in TypeMap.")
+        .clone()
+}
+
+/// Serve `/metrics` and `/healthz` on an address, until the process exits.
+pub async fn serve(address: SocketAddr, metrics: Arc<Metrics>, shards: Shards) {
+    let builder = match Server::try_bind(&address) {
+        Ok(builder) => builder,
+        Err(why) => {
+            error!(address = %address, error = %why, "Could not start the metrics server");
+            return;
+        }
+    };
+
+    let service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        let shards = shards.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                respond(request, metrics.clone(), shards.clone())
+            }))
+        }
+    });
+
+    info!(address = %address, "Serving metrics");
+    if let Err(why) = builder.serve(service).await {
+        error!(address = %address, error = %why, "The metrics server stopped");
+    }
+}
+
+async fn respond(
+    request: Request<Body>,
+    metrics: Arc<Metrics>,
+    shards: Shards,
+) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            let body = metrics.render(&*shards.lock().await);
+            Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(Body::from(body))
+        }
+        // Healthy when every shard of this process is connected to the gateway
+        (&Method::GET, "/healthz") => {
+            let shards = shards.lock().await;
+            let connected = !shards.is_empty()
+                && shards
+                    .values()
+                    .all(|info| info.stage == ConnectionStage::Connected);
+            let status = if connected {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            Response::builder()
+                .status(status)
+                .body(Body::from(shard_status(&shards)))
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.expect("the response is valid"))
+}
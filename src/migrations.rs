@@ -0,0 +1,290 @@
+use sqlite::Connection;
+use tracing::info;
+
+use crate::error::{Error, Result};
+
+type Migration = fn(&Connection) -> sqlite::Result<()>;
+
+/// Every schema change, in the order they are applied.
+///
+/// The schema version of a database is the number of migrations applied to it. Never edit
+/// or reorder a migration that was released, add a new one at the end instead.
+const MIGRATIONS: [(&str, Migration); 5] = [
+    ("initial schema", initial_schema),
+    ("guild ids on channel settings", guild_ids),
+    ("command prefixes", prefixes),
+    ("notice removal queue", notice_removals),
+    ("notice grouping and cooldown", notice_grouping),
+];
+
+/// The schema version this build migrates databases to.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Bring a database up to [`SCHEMA_VERSION`], refusing databases from a newer build.
+pub fn migrate(connection: &Connection) -> Result<()> {
+    connection.execute("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)")?;
+    let version = schema_version(connection)?;
+
+    if version > SCHEMA_VERSION {
+        return Err(Error::SchemaTooNew {
+            version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    for (index, (name, migration)) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let version = index as i64 + 1;
+
+        // A migration and its version bump are applied together or not at all. Another process
+        // sharing the database may have applied it while this one waited for the write lock.
+        connection.execute("BEGIN IMMEDIATE")?;
+        if schema_version(connection)? >= version {
+            connection.execute("COMMIT")?;
+            continue;
+        }
+        info!(version, name, "Migrating the database");
+        let result = migration(connection).and_then(|_| set_schema_version(connection, version));
+        match result {
+            Ok(()) => connection.execute("COMMIT")?,
+            Err(source) => {
+                connection.execute("ROLLBACK")?;
+                return Err(Error::Migration {
+                    version,
+                    name,
+                    source,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The version recorded in the database, 0 for databases from before migrations.
+fn schema_version(connection: &Connection) -> sqlite::Result<i64> {
+    let mut cursor = connection
+        .prepare("SELECT version FROM schema_version")?
+        .into_cursor();
+    Ok(cursor
+        .next()?
+        .and_then(|row| row[0].as_integer())
+        .unwrap_or(0))
+}
+
+fn set_schema_version(connection: &Connection, version: i64) -> sqlite::Result<()> {
+    connection.execute("DELETE FROM schema_version")?;
+    let mut statement = connection.prepare("INSERT INTO schema_version VALUES (?)")?;
+    statement.bind(1, version)?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Whether a table has a column, for changes that older builds may have made without migrations.
+fn has_column(connection: &Connection, table: &str, column: &str) -> sqlite::Result<bool> {
+    let mut cursor = connection
+        .prepare(format!("PRAGMA table_info({})", table))?
+        .into_cursor();
+    while let Some(row) = cursor.next()? {
+        if row[1].as_string() == Some(column) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The tables as they were before migrations. Databases from then already have some of them.
+fn initial_schema(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS banned_channels (channel_id INTEGER NOT NULL);
+        CREATE TABLE IF NOT EXISTS enforced_categories (category_id INTEGER NOT NULL);
+        CREATE TABLE IF NOT EXISTS excluded_channels (channel_id INTEGER NOT NULL);
+        CREATE TABLE IF NOT EXISTS channel_policies (
+            channel_id INTEGER PRIMARY KEY,
+            links INTEGER NOT NULL,
+            attachments INTEGER NOT NULL,
+            embeds INTEGER NOT NULL,
+            stickers INTEGER NOT NULL,
+            thread_text INTEGER NOT NULL,
+            max_caption INTEGER,
+            threads TEXT NOT NULL,
+            edits INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS attachment_types (
+            channel_id INTEGER NOT NULL,
+            type TEXT NOT NULL,
+            UNIQUE (channel_id, type)
+        );
+        CREATE TABLE IF NOT EXISTS domain_filters (
+            guild_id INTEGER NOT NULL,
+            channel_id INTEGER,
+            domain TEXT NOT NULL,
+            allow INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS notice_configs (
+            guild_id INTEGER NOT NULL,
+            channel_id INTEGER,
+            enabled INTEGER NOT NULL,
+            template TEXT NOT NULL,
+            lifetime INTEGER,
+            embed INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS guild_configs (
+            guild_id INTEGER PRIMARY KEY,
+            dm_offenders INTEGER NOT NULL,
+            log_channel INTEGER,
+            escalation_window INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS escalation_steps (
+            guild_id INTEGER NOT NULL,
+            violations INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            value INTEGER,
+            UNIQUE (guild_id, violations)
+        );
+        CREATE TABLE IF NOT EXISTS exemptions (
+            guild_id INTEGER NOT NULL,
+            channel_id INTEGER,
+            kind TEXT NOT NULL,
+            value INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS deletions (
+            guild_id INTEGER NOT NULL,
+            channel_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            message_id INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            rule TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            deleted_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS deletions_by_guild ON deletions (guild_id, deleted_at);
+        CREATE INDEX IF NOT EXISTS deletions_by_user ON deletions (guild_id, user_id, deleted_at);",
+    )
+}
+
+/// Settings keyed by channel alone get the guild they belong to, filled in when the guild loads.
+fn guild_ids(connection: &Connection) -> sqlite::Result<()> {
+    for table in [
+        "banned_channels",
+        "enforced_categories",
+        "excluded_channels",
+        "channel_policies",
+        "attachment_types",
+    ] {
+        if !has_column(connection, table, "guild_id")? {
+            connection.execute(format!("ALTER TABLE {} ADD COLUMN guild_id INTEGER", table))?;
+        }
+    }
+    Ok(())
+}
+
+/// Guilds can choose their own command prefix, `NULL` keeps the default.
+fn prefixes(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute("ALTER TABLE guild_configs ADD COLUMN prefix TEXT")
+}
+
+/// Notices waiting to be removed, so they're still removed after a restart.
+fn notice_removals(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE notice_removals (
+            guild_id INTEGER NOT NULL,
+            channel_id INTEGER NOT NULL,
+            message_id INTEGER PRIMARY KEY,
+            due_at INTEGER NOT NULL
+        );
+        CREATE INDEX notice_removals_by_due ON notice_removals (due_at);",
+    )
+}
+
+/// Notices can be grouped per channel and limited per member, both off for existing configs.
+fn notice_grouping(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "ALTER TABLE notice_configs ADD COLUMN coalesce_window INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE notice_configs ADD COLUMN cooldown INTEGER NOT NULL DEFAULT 0;",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory() -> Connection {
+        sqlite::open(":memory:").unwrap()
+    }
+
+    /// Every table, index and column, to compare a schema before and after.
+    fn schema(connection: &Connection) -> Vec<String> {
+        let mut cursor = connection
+            .prepare("SELECT sql FROM sqlite_master WHERE sql IS NOT NULL ORDER BY name")
+            .unwrap()
+            .into_cursor();
+        let mut schema = Vec::new();
+        while let Some(row) = cursor.next().unwrap() {
+            schema.push(row[0].as_string().unwrap().to_string());
+        }
+        schema
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest_version() {
+        let connection = memory();
+        migrate(&connection).unwrap();
+
+        assert_eq!(schema_version(&connection).unwrap(), SCHEMA_VERSION);
+        assert!(has_column(&connection, "banned_channels", "guild_id").unwrap());
+        assert!(has_column(&connection, "guild_configs", "prefix").unwrap());
+    }
+
+    #[test]
+    fn baseline_database_keeps_its_channels() {
+        let connection = memory();
+        connection
+            .execute(
+                "CREATE TABLE banned_channels (channel_id INTEGER NOT NULL);
+                INSERT INTO banned_channels VALUES (1), (2);",
+            )
+            .unwrap();
+
+        migrate(&connection).unwrap();
+
+        assert_eq!(schema_version(&connection).unwrap(), SCHEMA_VERSION);
+        assert!(has_column(&connection, "banned_channels", "guild_id").unwrap());
+        let mut cursor = connection
+            .prepare("SELECT channel_id, guild_id FROM banned_channels ORDER BY channel_id")
+            .unwrap()
+            .into_cursor();
+        let mut rows = Vec::new();
+        while let Some(row) = cursor.next().unwrap() {
+            rows.push((row[0].as_integer(), row[1].as_integer()));
+        }
+        // Claimed by their guild once it loads
+        assert_eq!(rows, [(Some(1), None), (Some(2), None)]);
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let connection = memory();
+        migrate(&connection).unwrap();
+        let before = schema(&connection);
+
+        migrate(&connection).unwrap();
+
+        assert_eq!(schema_version(&connection).unwrap(), SCHEMA_VERSION);
+        assert_eq!(schema(&connection), before);
+    }
+
+    #[test]
+    fn newer_database_is_refused() {
+        let connection = memory();
+        migrate(&connection).unwrap();
+        set_schema_version(&connection, SCHEMA_VERSION + 1).unwrap();
+
+        match migrate(&connection) {
+            Err(Error::SchemaTooNew { version, supported }) => {
+                assert_eq!(version, SCHEMA_VERSION + 1);
+                assert_eq!(supported, SCHEMA_VERSION);
+            }
+            result => panic!("expected SchemaTooNew, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::Timestamp;
+use serenity::prelude::Mentionable;
+use serenity::utils::Colour;
+use tracing::warn;
+
+use crate::metrics;
+use crate::policy::Violation;
+
+/// Shown instead of the text of a removed message that had none, like one with only an attachment
+const NO_TEXT: &str = "*No text*";
+/// The longest embed description Discord accepts, in characters
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+
+/// Which deletions `~shut log` lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeletionFilter {
+    All,
+    User(UserId),
+    Channel(ChannelId),
+}
+
+/// A removed message, as kept in the `deletions` table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deletion {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub user_id: UserId,
+    pub message_id: MessageId,
+    pub content: String,
+    pub rule: String,
+    pub reason: String,
+    /// Unix time the message was removed at
+    pub deleted_at: i64,
+}
+
+impl Deletion {
+    pub fn new(guild_id: GuildId, msg: &Message, violation: &Violation) -> Self {
+        Deletion {
+            guild_id,
+            channel_id: msg.channel_id,
+            user_id: msg.author.id,
+            message_id: msg.id,
+            content: msg.content.clone(),
+            rule: violation.rule().to_string(),
+            reason: violation.to_string(),
+            deleted_at: Timestamp::now().unix_timestamp(),
+        }
+    }
+
+    /// One line summary for `~shut log`.
+    pub fn summary(&self) -> String {
+        // Keep a long history readable
+        let mut content: String = self.content.chars().take(80).collect();
+        if content.len() < self.content.len() {
+            content.push('…');
+        }
+        if content.is_empty() {
+            content.push_str(NO_TEXT);
+        }
+
+        format!(
+            "<t:{}:R> {} in {} ({}): {}",
+            self.deleted_at,
+            self.user_id.mention(),
+            self.channel_id.mention(),
+            self.rule,
+            content.replace('\n', " ")
+        )
+    }
+}
+
+/// The text of a removed message as an embed description, cut to fit.
+pub fn embed_text(content: &str) -> String {
+    if content.is_empty() {
+        return NO_TEXT.to_string();
+    }
+    content.chars().take(EMBED_DESCRIPTION_LIMIT).collect()
+}
+
+/// Post a removed message to the guild's moderation log channel.
+pub async fn post_deletion(
+    ctx: &Context,
+    log_channel: ChannelId,
+    msg: &Message,
+    deletion: &Deletion,
+) {
+    let content = embed_text(&deletion.content);
+    let result = log_channel
+        .send_message(&ctx, |m| {
+            m.embed(|e| {
+                e.author(|a| a.name(msg.author.tag()).icon_url(msg.author.face()))
+                    .title("Message removed")
+                    .description(content)
+                    .field("Author", msg.author.mention(), true)
+                    .field("Channel", msg.channel_id.mention(), true)
+                    .field("Rule", &deletion.rule, true)
+                    .field("Reason", &deletion.reason, false)
+                    .footer(|f| {
+                        f.text(format!(
+                            "User ID: {} | Message ID: {}",
+                            deletion.user_id, deletion.message_id
+                        ))
+                    })
+                    .timestamp(msg.timestamp)
+                    .colour(Colour::RED)
+            })
+        })
+        .await;
+
+    if let Err(why) = result {
+        metrics::get(ctx).await.discord_error();
+        warn!(
+            guild = %deletion.guild_id,
+            channel = %log_channel,
+            error = %why,
+            "Could not post to the log channel"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embed_text_fits_the_limit() {
+        assert_eq!(embed_text(""), NO_TEXT);
+        assert_eq!(embed_text("look at this"), "look at this");
+
+        // Counted in characters, not bytes
+        let long = "é".repeat(EMBED_DESCRIPTION_LIMIT + 10);
+        let text = embed_text(&long);
+        assert_eq!(text.chars().count(), EMBED_DESCRIPTION_LIMIT);
+        assert!(long.starts_with(&text));
+    }
+}
@@ -0,0 +1,340 @@
+use serenity::client::Context;
+use serenity::http::Http;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::Timestamp;
+use serenity::prelude::{Mentionable, RwLock, TypeMapKey};
+use serenity::utils::Colour;
+use tracing::{error, warn};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::error::{self, Result};
+use crate::metrics::{self, Metrics};
+use crate::modlog;
+use crate::policy::{ChannelRules, Violation};
+use crate::settings::{self, Settings};
+
+/// How often queued notices are checked for removal
+const REMOVAL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How the bot tells people their message was removed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NoticeConfig {
+    /// Whether to post a notice at all
+    pub enabled: bool,
+    /// Text of the notice, see [`NoticeConfig::render`] for the placeholders
+    pub template: String,
+    /// How long the notice stays up, `None` keeps it forever
+    pub lifetime: Option<u64>,
+    /// Post the notice as an embed instead of plain text
+    pub embed: bool,
+    /// Seconds to wait for more removals in the channel, to post one notice for all of them
+    pub coalesce_window: u64,
+    /// Seconds before a member gets another notice in the channel
+    pub cooldown: u64,
+}
+
+impl Default for NoticeConfig {
+    fn default() -> Self {
+        NoticeConfig {
+            enabled: true,
+            template: "{user} SHUT!".to_string(),
+            lifetime: Some(3),
+            embed: false,
+            coalesce_window: 0,
+            cooldown: 0,
+        }
+    }
+}
+
+impl NoticeConfig {
+    pub const PLACEHOLDERS: [&'static str; 4] = ["{user}", "{channel}", "{rule}", "{reason}"];
+
+    /// Fill in `{user}`, `{channel}`, `{rule}` and `{reason}` for removed messages in a channel.
+    ///
+    /// A notice for several messages lists every offender, rule and reason once.
+    pub fn render(&self, channel: ChannelId, offenses: &[Offense]) -> String {
+        let users = offenses
+            .iter()
+            .map(|offense| offense.user.mention().to_string());
+        let rules = offenses.iter().map(|offense| offense.rule.to_string());
+        let reasons = offenses.iter().map(|offense| offense.reason.clone());
+        self.template
+            .replace("{user}", &join_list(users))
+            .replace("{channel}", &channel.mention().to_string())
+            .replace("{rule}", &join_list(rules))
+            .replace("{reason}", &join_list(reasons))
+    }
+
+    pub fn describe(&self) -> String {
+        let lifetime = match self.lifetime {
+            _ if !self.enabled => "not posted".to_string(),
+            Some(seconds) => format!("removed after {} seconds", seconds),
+            None => "never removed".to_string(),
+        };
+        let window = match self.coalesce_window {
+            0 => "off".to_string(),
+            seconds => format!("{} seconds", seconds),
+        };
+        let cooldown = match self.cooldown {
+            0 => "off".to_string(),
+            seconds => format!("{} seconds", seconds),
+        };
+        format!(
+            "Template: `{}`\nLifetime: {}\nEmbed: {}\nGrouping window: {}\nCooldown per member: {}",
+            self.template,
+            lifetime,
+            if self.embed { "on" } else { "off" },
+            window,
+            cooldown
+        )
+    }
+}
+
+/// Tell the author why their message was removed, following the notice config.
+///
+/// With a grouping window the first removal waits for others in the channel and posts one notice
+/// for all of them, and members still on cooldown in the channel don't get a notice at all.
+pub async fn send_notice(
+    ctx: &Context,
+    guild_id: GuildId,
+    msg: &Message,
+    violation: &Violation,
+    config: &NoticeConfig,
+) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    // Acquire data lock
+    let notice_state = {
+        let data = ctx.data.read().await;
+        data.get::<NoticeState>()
+            .expect("Expected NoticeState in TypeMap.")
+            .clone()
+    };
+
+    let now = Timestamp::now().unix_timestamp();
+    if !notice_state.start_cooldown(msg.channel_id, msg.author.id, now, config.cooldown) {
+        return Ok(());
+    }
+
+    let offense = Offense::new(msg, violation);
+    let offenses = if config.coalesce_window == 0 {
+        vec![offense]
+    } else {
+        if !notice_state.join_batch(msg.channel_id, offense) {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(config.coalesce_window)).await;
+        notice_state.take_batch(msg.channel_id)
+    };
+
+    let text = config.render(msg.channel_id, &offenses);
+    let reply_msg = msg
+        .channel_id
+        .send_message(&ctx, |m| {
+            if config.embed {
+                m.embed(|e| e.description(&text).colour(Colour::RED))
+            } else {
+                m.content(&text)
+            }
+        })
+        .await?;
+    metrics::get(ctx).await.notice_sent();
+
+    if let Some(lifetime) = config.lifetime {
+        let settings_lock = settings::get(ctx).await;
+
+        // Queued instead of waited for, so the notice is still removed after a restart
+        let removal = NoticeRemoval {
+            guild_id,
+            channel_id: reply_msg.channel_id,
+            message_id: reply_msg.id,
+            due_at: Timestamp::now().unix_timestamp() + lifetime as i64,
+        };
+        settings_lock.read().await.queue_notice_removal(&removal)?;
+    }
+    Ok(())
+}
+
+/// A removed message a notice is about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Offense {
+    pub user: UserId,
+    pub rule: &'static str,
+    pub reason: String,
+}
+
+impl Offense {
+    pub fn new(msg: &Message, violation: &Violation) -> Self {
+        Offense {
+            user: msg.author.id,
+            rule: violation.rule(),
+            reason: violation.to_string(),
+        }
+    }
+}
+
+/// Notices being grouped per channel, and until when members are on cooldown in a channel.
+#[derive(Default)]
+pub struct NoticeState {
+    batches: Mutex<HashMap<ChannelId, Vec<Offense>>>,
+    cooldowns: Mutex<HashMap<(ChannelId, UserId), i64>>,
+}
+
+impl TypeMapKey for NoticeState {
+    type Value = Arc<NoticeState>;
+}
+
+impl NoticeState {
+    /// Whether a member can get a notice in a channel at a unix time, starting their cooldown if so.
+    fn start_cooldown(&self, channel: ChannelId, user: UserId, now: i64, cooldown: u64) -> bool {
+        let mut cooldowns = self
+            .cooldowns
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        cooldowns.retain(|_, until| *until > now);
+        if cooldowns.contains_key(&(channel, user)) {
+            return false;
+        }
+        if cooldown > 0 {
+            cooldowns.insert((channel, user), now + cooldown as i64);
+        }
+        true
+    }
+
+    /// Add a removal to the channel's next notice. Returns true if it's the first one, whoever
+    /// adds the first one posts the notice when the window ends.
+    fn join_batch(&self, channel: ChannelId, offense: Offense) -> bool {
+        let mut batches = self.batches.lock().unwrap_or_else(PoisonError::into_inner);
+        let batch = batches.entry(channel).or_default();
+        batch.push(offense);
+        batch.len() == 1
+    }
+
+    fn take_batch(&self, channel: ChannelId) -> Vec<Offense> {
+        let mut batches = self.batches.lock().unwrap_or_else(PoisonError::into_inner);
+        batches.remove(&channel).unwrap_or_default()
+    }
+}
+
+/// `a`, `a and b` or `a, b and c`, leaving out repeats.
+fn join_list(items: impl Iterator<Item = String>) -> String {
+    let mut unique: Vec<String> = Vec::new();
+    for item in items {
+        if !unique.contains(&item) {
+            unique.push(item);
+        }
+    }
+
+    match unique.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+/// A notice waiting to be removed, as kept in the `notice_removals` table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoticeRemoval {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    /// Unix time the notice is removed at
+    pub due_at: i64,
+}
+
+/// Remove queued notices once they're due, until the process exits.
+pub async fn run_removals(
+    http: Arc<Http>,
+    settings_lock: Arc<RwLock<Settings>>,
+    metrics: Arc<Metrics>,
+) {
+    loop {
+        tokio::time::sleep(REMOVAL_INTERVAL).await;
+        remove_notices(
+            &http,
+            &settings_lock,
+            &metrics,
+            Timestamp::now().unix_timestamp(),
+        )
+        .await;
+    }
+}
+
+/// Remove the queued notices of loaded guilds that are due by a unix time.
+///
+/// Notices that can't be removed because of a Discord outage stay queued to be tried again.
+pub async fn remove_notices(
+    http: &Http,
+    settings_lock: &RwLock<Settings>,
+    metrics: &Metrics,
+    now: i64,
+) {
+    let due = settings_lock.read().await.due_notice_removals(now);
+    let due = match due {
+        Ok(due) => due,
+        Err(why) => {
+            error!(error = %why, "Could not read the notice removal queue");
+            return;
+        }
+    };
+
+    for removal in due {
+        if let Err(why) = removal
+            .channel_id
+            .delete_message(http, removal.message_id)
+            .await
+        {
+            metrics.discord_error();
+            warn!(
+                guild = %removal.guild_id,
+                channel = %removal.channel_id,
+                error = %why,
+                "Could not remove a notice"
+            );
+            // Try again later, unless Discord rejected it like for a notice that's already gone
+            if !error::is_client_error(&why) {
+                continue;
+            }
+        }
+
+        let result = settings_lock
+            .read()
+            .await
+            .dequeue_notice_removal(removal.message_id);
+        if let Err(why) = result {
+            error!(guild = %removal.guild_id, error = %why, "Could not dequeue a removed notice");
+        }
+    }
+}
+
+/// DM the author a copy of their removed message, with the reason and the channel's rules.
+///
+/// Returns false if the DM couldn't be sent, usually because the author has DMs closed.
+pub async fn dm_offender(
+    ctx: &Context,
+    msg: &Message,
+    violation: &Violation,
+    rules: &ChannelRules,
+) -> bool {
+    let content = modlog::embed_text(&msg.content);
+
+    msg.author
+        .direct_message(&ctx, |m| {
+            m.embed(|e| {
+                e.title("Your message was removed")
+                    .description(content)
+                    .field("Channel", msg.channel_id.mention(), true)
+                    .field("Reason", violation, true)
+                    .field("Channel rules", rules.describe(), false)
+                    .colour(Colour::RED)
+            })
+        })
+        .await
+        .is_ok()
+}
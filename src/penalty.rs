@@ -0,0 +1,287 @@
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, RoleId};
+use serenity::model::Timestamp;
+use serenity::prelude::Mentionable;
+use tracing::{info, warn};
+
+use std::fmt;
+
+use crate::metrics;
+
+/// What happens to a member who keeps breaking the rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Penalty {
+    /// Tell them further violations will be punished
+    Warn,
+    Timeout {
+        minutes: u64,
+    },
+    RemoveRole(RoleId),
+    Kick,
+}
+
+impl Penalty {
+    /// Parse a penalty from command arguments like `timeout 10` or `removerole @Artist`.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let usage = "Expected `warn`, `timeout <minutes>`, `removerole <@role>` or `kick`";
+        let kind = args.first().map(|kind| kind.to_ascii_lowercase());
+        let value = args.get(1);
+
+        match (kind.as_deref(), value) {
+            (Some("warn"), None) => Ok(Penalty::Warn),
+            (Some("kick"), None) => Ok(Penalty::Kick),
+            (Some("timeout"), Some(minutes)) => match minutes.parse() {
+                // Discord allows timeouts of up to 28 days
+                Ok(minutes) if minutes > 0 && minutes <= 28 * 24 * 60 => {
+                    Ok(Penalty::Timeout { minutes })
+                }
+                _ => Err("A timeout lasts between 1 minute and 28 days".to_string()),
+            },
+            (Some("removerole"), Some(role)) => role
+                .parse()
+                .map(Penalty::RemoveRole)
+                .map_err(|_| usage.to_string()),
+            _ => Err(usage.to_string()),
+        }
+    }
+
+    /// The `kind` and `value` columns this penalty is stored as.
+    pub fn to_row(self) -> (&'static str, Option<i64>) {
+        match self {
+            Penalty::Warn => ("warn", None),
+            Penalty::Timeout { minutes } => ("timeout", Some(minutes as i64)),
+            Penalty::RemoveRole(role) => ("removerole", Some(role.0 as i64)),
+            Penalty::Kick => ("kick", None),
+        }
+    }
+
+    pub fn from_row(kind: &str, value: Option<i64>) -> Option<Self> {
+        match (kind, value) {
+            ("warn", _) => Some(Penalty::Warn),
+            ("timeout", Some(minutes)) => Some(Penalty::Timeout {
+                minutes: minutes as u64,
+            }),
+            ("removerole", Some(role)) => Some(Penalty::RemoveRole(RoleId(role as u64))),
+            ("kick", _) => Some(Penalty::Kick),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Penalty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Penalty::Warn => f.write_str("warn"),
+            Penalty::Timeout { minutes } => write!(f, "time out for {} minutes", minutes),
+            Penalty::RemoveRole(role) => write!(f, "remove {}", role.mention()),
+            Penalty::Kick => f.write_str("kick"),
+        }
+    }
+}
+
+/// A penalty applied when a member reaches a number of violations within the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EscalationStep {
+    pub violations: u32,
+    pub penalty: Penalty,
+}
+
+/// The step to apply now that a member reached `count` violations.
+///
+/// Each step applies once, when its count is reached, except the last one which
+/// applies again on every violation after it.
+pub fn step_for(steps: &[EscalationStep], count: u32) -> Option<EscalationStep> {
+    let last = steps.iter().map(|step| step.violations).max()?;
+    steps
+        .iter()
+        .filter(|step| step.violations <= count)
+        .max_by_key(|step| step.violations)
+        .filter(|step| step.violations == count || step.violations == last)
+        .copied()
+}
+
+/// Apply a penalty to the author of a removed message, reporting the outcome to the log channel.
+pub async fn apply(
+    ctx: &Context,
+    guild_id: GuildId,
+    msg: &Message,
+    step: EscalationStep,
+    log_channel: Option<ChannelId>,
+) {
+    let reason = format!(
+        "{} messages removed by SHUT within the escalation window",
+        step.violations
+    );
+
+    let result = match step.penalty {
+        Penalty::Warn => {
+            let warning = format!(
+                "{} you've had {} messages removed recently, further violations will be punished",
+                msg.author.mention(),
+                step.violations
+            );
+            // Fall back to the channel when DMs are closed
+            match msg
+                .author
+                .direct_message(&ctx, |m| m.content(&warning))
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(_) => msg.channel_id.say(&ctx, &warning).await.map(|_| ()),
+            }
+        }
+        Penalty::Timeout { minutes } => {
+            let until = Timestamp::now().unix_timestamp() + minutes as i64 * 60;
+            match (
+                guild_id.member(&ctx, msg.author.id).await,
+                Timestamp::from_unix_timestamp(until),
+            ) {
+                (Ok(mut member), Ok(until)) => {
+                    member
+                        .disable_communication_until_datetime(&ctx, until)
+                        .await
+                }
+                (Err(why), _) => Err(why),
+                (_, Err(_)) => Ok(()),
+            }
+        }
+        Penalty::RemoveRole(role) => match guild_id.member(&ctx, msg.author.id).await {
+            Ok(mut member) => member.remove_role(&ctx, role).await,
+            Err(why) => Err(why),
+        },
+        Penalty::Kick => {
+            guild_id
+                .kick_with_reason(&ctx, msg.author.id, &reason)
+                .await
+        }
+    };
+
+    match &result {
+        Ok(()) => info!(
+            guild = %guild_id,
+            channel = %msg.channel_id,
+            user = %msg.author.id,
+            action = %step.penalty,
+            "Applied a penalty"
+        ),
+        Err(why) => {
+            metrics::get(ctx).await.discord_error();
+            warn!(
+                guild = %guild_id,
+                channel = %msg.channel_id,
+                user = %msg.author.id,
+                action = %step.penalty,
+                error = %why,
+                "Could not apply a penalty"
+            )
+        }
+    }
+
+    let log_channel = match log_channel {
+        Some(log_channel) => log_channel,
+        None => return,
+    };
+    let report = match &result {
+        Ok(()) => format!(
+            "Applied penalty to {}: {} ({})",
+            msg.author.mention(),
+            step.penalty,
+            reason
+        ),
+        Err(why) => format!(
+            "Could not apply penalty to {}: {} ({:?})",
+            msg.author.mention(),
+            step.penalty,
+            why
+        ),
+    };
+    let sent = log_channel
+        .send_message(&ctx, |m| {
+            m.content(&report).allowed_mentions(|am| am.empty_parse())
+        })
+        .await;
+    if let Err(why) = sent {
+        metrics::get(ctx).await.discord_error();
+        warn!(
+            guild = %guild_id,
+            channel = %log_channel,
+            error = %why,
+            "Could not post to the log channel"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Warn at 3, time out at 5, kick at 8
+    fn steps() -> Vec<EscalationStep> {
+        vec![
+            EscalationStep {
+                violations: 3,
+                penalty: Penalty::Warn,
+            },
+            EscalationStep {
+                violations: 5,
+                penalty: Penalty::Timeout { minutes: 10 },
+            },
+            EscalationStep {
+                violations: 8,
+                penalty: Penalty::Kick,
+            },
+        ]
+    }
+
+    fn penalty(count: u32) -> Option<Penalty> {
+        step_for(&steps(), count).map(|step| step.penalty)
+    }
+
+    #[test]
+    fn nothing_below_the_first_step() {
+        assert_eq!(penalty(0), None);
+        assert_eq!(penalty(1), None);
+        assert_eq!(penalty(2), None);
+    }
+
+    #[test]
+    fn each_step_applies_at_its_count() {
+        assert_eq!(penalty(3), Some(Penalty::Warn));
+        assert_eq!(penalty(5), Some(Penalty::Timeout { minutes: 10 }));
+        assert_eq!(penalty(8), Some(Penalty::Kick));
+    }
+
+    #[test]
+    fn nothing_between_steps() {
+        assert_eq!(penalty(4), None);
+        assert_eq!(penalty(6), None);
+        assert_eq!(penalty(7), None);
+    }
+
+    #[test]
+    fn last_step_repeats_past_it() {
+        assert_eq!(penalty(9), Some(Penalty::Kick));
+        assert_eq!(penalty(100), Some(Penalty::Kick));
+    }
+
+    #[test]
+    fn no_steps_no_penalty() {
+        assert_eq!(step_for(&[], 5), None);
+    }
+
+    #[test]
+    fn order_of_the_steps_does_not_matter() {
+        let mut steps = steps();
+        steps.reverse();
+        assert_eq!(step_for(&steps, 4), None);
+        assert_eq!(
+            step_for(&steps, 5).map(|step| step.penalty),
+            Some(Penalty::Timeout { minutes: 10 })
+        );
+        assert_eq!(
+            step_for(&steps, 9).map(|step| step.penalty),
+            Some(Penalty::Kick)
+        );
+    }
+}
@@ -0,0 +1,704 @@
+use serenity::model::channel::{Attachment, Message};
+use url::Url;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Which kinds of content count as media in an enforced channel.
+///
+/// A message is kept if it contains at least one kind of content the policy
+/// allows; everything else is treated as chat and removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelPolicy {
+    pub links: bool,
+    pub attachments: bool,
+    pub embeds: bool,
+    pub stickers: bool,
+    /// Allow plain text replies when the channel is a thread
+    pub thread_text: bool,
+    /// Longest caption allowed next to media, in characters, not counting links
+    pub max_caption: Option<u32>,
+    /// How the policy applies to threads and forum posts in the channel
+    pub threads: ThreadMode,
+    /// Check messages again when they are edited
+    pub edits: bool,
+}
+
+impl Default for ChannelPolicy {
+    // Matches the behaviour from before policies existed: links and attachments only
+    fn default() -> Self {
+        ChannelPolicy {
+            links: true,
+            attachments: true,
+            embeds: false,
+            stickers: false,
+            thread_text: false,
+            max_caption: None,
+            threads: ThreadMode::Enforce,
+            edits: false,
+        }
+    }
+}
+
+/// Where a message posted in a thread or forum post sits in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadMessage {
+    /// The first message. A thread started from a message has that message in its parent channel
+    /// instead, where it's checked like the rest of the channel.
+    Starter,
+    Reply,
+}
+
+/// How a channel's policy applies to the threads and forum posts created in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadMode {
+    /// Every message in a thread is checked like a message in the channel
+    Enforce,
+    /// Threads aren't checked at all
+    Ignore,
+    /// Only the message that starts a forum post or thread is checked
+    Starter,
+}
+
+impl ThreadMode {
+    pub const ALL: [ThreadMode; 3] = [ThreadMode::Enforce, ThreadMode::Ignore, ThreadMode::Starter];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ThreadMode::Enforce => "enforce",
+            ThreadMode::Ignore => "ignore",
+            ThreadMode::Starter => "starter",
+        }
+    }
+}
+
+impl fmt::Display for ThreadMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ThreadMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ThreadMode::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                format!(
+                    "Unknown thread mode `{}`, expected `enforce`, `ignore` or `starter`",
+                    s
+                )
+            })
+    }
+}
+
+/// A single editable rule of a [`ChannelPolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    Links,
+    Attachments,
+    Embeds,
+    Stickers,
+    ThreadText,
+    Edits,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 6] = [
+        Rule::Links,
+        Rule::Attachments,
+        Rule::Embeds,
+        Rule::Stickers,
+        Rule::ThreadText,
+        Rule::Edits,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Links => "links",
+            Rule::Attachments => "attachments",
+            Rule::Embeds => "embeds",
+            Rule::Stickers => "stickers",
+            Rule::ThreadText => "thread_text",
+            Rule::Edits => "edits",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rule::ALL
+            .into_iter()
+            .find(|rule| rule.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Rule::ALL.iter().map(|rule| rule.name()).collect();
+                format!(
+                    "Unknown rule `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// An entry in a channel's attachment allow-list.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AttachmentType {
+    /// A MIME type such as `image/png`, or a whole family such as `image/*`
+    Mime {
+        kind: String,
+        subtype: Option<String>,
+    },
+    /// A file extension, stored without the leading dot
+    Extension(String),
+}
+
+impl AttachmentType {
+    pub fn matches(&self, attachment: &Attachment) -> bool {
+        match self {
+            AttachmentType::Mime { kind, subtype } => {
+                // Strip parameters like `; charset=utf-8`
+                let content_type = match &attachment.content_type {
+                    Some(content_type) => content_type.split(';').next().unwrap_or("").trim(),
+                    None => return false,
+                };
+                let (actual_kind, actual_subtype) =
+                    content_type.split_once('/').unwrap_or((content_type, ""));
+
+                actual_kind.eq_ignore_ascii_case(kind)
+                    && match subtype {
+                        Some(subtype) => actual_subtype.eq_ignore_ascii_case(subtype),
+                        None => true,
+                    }
+            }
+            AttachmentType::Extension(extension) => matches!(
+                attachment.filename.rsplit_once('.'),
+                Some((_, actual)) if actual.eq_ignore_ascii_case(extension)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for AttachmentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachmentType::Mime {
+                kind,
+                subtype: Some(subtype),
+            } => write!(f, "{}/{}", kind, subtype),
+            AttachmentType::Mime {
+                kind,
+                subtype: None,
+            } => write!(f, "{}/*", kind),
+            AttachmentType::Extension(extension) => write!(f, ".{}", extension),
+        }
+    }
+}
+
+impl FromStr for AttachmentType {
+    type Err = String;
+
+    /// Accepts `image/png`, `image/*`, `.png`, or a bare `image`, `video` or `audio`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let valid = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-+._".contains(c))
+        };
+
+        if let Some((kind, subtype)) = s.split_once('/') {
+            if valid(kind) && (subtype == "*" || valid(subtype)) {
+                return Ok(AttachmentType::Mime {
+                    kind: kind.to_string(),
+                    subtype: (subtype != "*").then(|| subtype.to_string()),
+                });
+            }
+        } else if let Some(extension) = s.strip_prefix('.') {
+            if valid(extension) {
+                return Ok(AttachmentType::Extension(extension.to_string()));
+            }
+        } else if matches!(s.as_str(), "image" | "video" | "audio") {
+            return Ok(AttachmentType::Mime {
+                kind: s,
+                subtype: None,
+            });
+        }
+
+        Err(format!(
+            "`{}` is not a MIME type (`image/png`, `video/*`) or extension (`.png`)",
+            s
+        ))
+    }
+}
+
+/// Domains that do or don't count as media when linked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DomainFilter {
+    /// When not empty, only links to these domains count as media
+    pub allow: Vec<String>,
+    /// Links to these domains never count as media
+    pub deny: Vec<String>,
+}
+
+impl DomainFilter {
+    pub fn allows(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.trim_end_matches('.').to_ascii_lowercase(),
+            None => return false,
+        };
+        // A domain also covers its subdomains
+        let matches = |domain: &String| {
+            host == *domain
+                || (host.ends_with(domain.as_str())
+                    && host[..host.len() - domain.len()].ends_with('.'))
+        };
+
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+/// Normalize a user supplied domain, accepting full URLs and `*.` wildcards.
+pub fn parse_domain(s: &str) -> Option<String> {
+    let s = s.trim().trim_start_matches('<').trim_end_matches('>');
+    let domain = match Url::parse(s) {
+        Ok(url) if url.has_host() => url.host_str()?.to_string(),
+        _ => s.trim_start_matches("*.").trim_matches('.').to_string(),
+    };
+    let domain = domain.to_ascii_lowercase();
+
+    let valid = domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+    if valid {
+        Some(domain)
+    } else {
+        None
+    }
+}
+
+/// The http(s) link in a single whitespace separated word, if any.
+fn link_in_word(word: &str) -> Option<Url> {
+    // Links can be wrapped in markdown, spoilers or `<>` to suppress embeds
+    let start = word.find("https://").or_else(|| word.find("http://"))?;
+    let candidate = word[start..].trim_end_matches(|c: char| ">)]|*_~`'\",.!?".contains(c));
+    Url::parse(candidate)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+}
+
+/// All http(s) links in a piece of text.
+pub fn links(content: &str) -> Vec<Url> {
+    content
+        .split_whitespace()
+        .filter_map(link_in_word)
+        .collect()
+}
+
+/// The length of a message's text in characters, ignoring links and surrounding whitespace.
+pub fn caption_length(content: &str) -> usize {
+    let words: Vec<_> = content
+        .split_whitespace()
+        .filter(|word| link_in_word(word).is_none())
+        .collect();
+    words.join(" ").chars().count()
+}
+
+/// Everything that decides whether a message may stay in an enforced channel.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelRules {
+    pub policy: ChannelPolicy,
+    /// When not empty, only attachments matching one of these count as media, and messages with
+    /// any other attachment are removed
+    pub attachment_types: Vec<AttachmentType>,
+    pub domains: DomainFilter,
+}
+
+impl ChannelRules {
+    /// Explain the rules in plain words, for people whose message was removed.
+    pub fn describe(&self) -> String {
+        let policy = &self.policy;
+        let mut media = Vec::new();
+        if policy.links {
+            media.push(if self.domains.allow.is_empty() {
+                "links".to_string()
+            } else {
+                format!("links to {}", self.domains.allow.join(", "))
+            });
+        }
+        if policy.attachments {
+            media.push(if self.attachment_types.is_empty() {
+                "attachments".to_string()
+            } else {
+                let types: Vec<_> = self
+                    .attachment_types
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                format!("attachments ({})", types.join(", "))
+            });
+        }
+        if policy.embeds {
+            media.push("embeds".to_string());
+        }
+        if policy.stickers {
+            media.push("stickers".to_string());
+        }
+
+        let mut description = if media.is_empty() {
+            "Nothing counts as media here.".to_string()
+        } else {
+            format!("Only messages with {} are allowed.", media.join(", "))
+        };
+        if let Some(limit) = policy.max_caption {
+            description.push_str(&format!(
+                " Text next to media can be at most {} characters, not counting links.",
+                limit
+            ));
+        }
+        match policy.threads {
+            ThreadMode::Enforce if policy.thread_text => {
+                description.push_str(" Text replies are fine in threads.")
+            }
+            ThreadMode::Enforce => {}
+            ThreadMode::Ignore => description.push_str(" Threads aren't checked."),
+            ThreadMode::Starter => description
+                .push_str(" In threads and forum posts only the first message is checked."),
+        }
+        description
+    }
+
+    /// Whether [`ChannelRules::check`] treats the first message of a thread differently from
+    /// the others, so that it's worth finding out which one a message is.
+    pub fn tells_starter_apart(&self) -> bool {
+        match self.policy.threads {
+            ThreadMode::Enforce => self.policy.thread_text,
+            ThreadMode::Ignore => false,
+            ThreadMode::Starter => true,
+        }
+    }
+
+    /// Check a message against these rules, passing where it sits in a thread if it was posted
+    /// in one.
+    pub fn check(&self, msg: &Message, thread: Option<ThreadMessage>) -> Result<(), Violation> {
+        let policy = &self.policy;
+
+        if let Some(thread) = thread {
+            let starter = thread == ThreadMessage::Starter;
+            match policy.threads {
+                ThreadMode::Ignore => return Ok(()),
+                ThreadMode::Starter if !starter => return Ok(()),
+                _ => {}
+            }
+            // Replies can be plain text, the post itself still needs media
+            if policy.thread_text && !starter {
+                return Ok(());
+            }
+        }
+
+        // Every attachment has to be of an allowed type, an image or a link can't carry an
+        // executable along, even in channels where attachments don't count as media
+        let attachments_allowed = msg.attachments.iter().all(|attachment| {
+            self.attachment_types.is_empty()
+                || self
+                    .attachment_types
+                    .iter()
+                    .any(|ty| ty.matches(attachment))
+        });
+        if !attachments_allowed {
+            return Err(Violation::NoMedia);
+        }
+
+        let has_media = (policy.links
+            && links(&msg.content)
+                .iter()
+                .any(|url| self.domains.allows(url)))
+            || (policy.attachments && !msg.attachments.is_empty())
+            || (policy.embeds && !msg.embeds.is_empty())
+            || (policy.stickers && !msg.sticker_items.is_empty());
+
+        if !has_media {
+            return Err(Violation::NoMedia);
+        }
+
+        if let Some(limit) = policy.max_caption {
+            let length = caption_length(&msg.content);
+            if length > limit as usize {
+                return Err(Violation::CaptionTooLong { length, limit });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a message was rejected by a [`ChannelPolicy`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The message contains nothing the channel accepts as media
+    NoMedia,
+    /// The message has media, but too much text next to it
+    CaptionTooLong { length: usize, limit: u32 },
+}
+
+impl Violation {
+    /// Short name of the rule that was broken
+    pub fn rule(&self) -> &'static str {
+        match self {
+            Violation::NoMedia => "media-only",
+            Violation::CaptionTooLong { .. } => "caption-limit",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::NoMedia => f.write_str("message contains no allowed media"),
+            Violation::CaptionTooLong { length, limit } => write!(
+                f,
+                "caption is {} characters long, the limit is {}",
+                length, limit
+            ),
+        }
+    }
+}
+
+impl ChannelPolicy {
+    pub fn get(&self, rule: Rule) -> bool {
+        match rule {
+            Rule::Links => self.links,
+            Rule::Attachments => self.attachments,
+            Rule::Embeds => self.embeds,
+            Rule::Stickers => self.stickers,
+            Rule::ThreadText => self.thread_text,
+            Rule::Edits => self.edits,
+        }
+    }
+
+    pub fn set(&mut self, rule: Rule, value: bool) {
+        match rule {
+            Rule::Links => self.links = value,
+            Rule::Attachments => self.attachments = value,
+            Rule::Embeds => self.embeds = value,
+            Rule::Stickers => self.stickers = value,
+            Rule::ThreadText => self.thread_text = value,
+            Rule::Edits => self.edits = value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::json::json;
+    use serenity::json::prelude::from_value;
+
+    use super::*;
+
+    fn attachment(filename: &str, content_type: Option<&str>) -> Attachment {
+        from_value(json!({
+            "id": "1",
+            "filename": filename,
+            "size": 1,
+            "url": "https://cdn.discordapp.com/attachments/1/1/file",
+            "proxy_url": "https://media.discordapp.net/attachments/1/1/file",
+            "content_type": content_type,
+        }))
+        .unwrap()
+    }
+
+    fn attachment_type(s: &str) -> AttachmentType {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn attachment_types_parse() {
+        assert_eq!(
+            attachment_type("Image/PNG"),
+            AttachmentType::Mime {
+                kind: "image".to_string(),
+                subtype: Some("png".to_string())
+            }
+        );
+        assert_eq!(
+            attachment_type(" image/* "),
+            AttachmentType::Mime {
+                kind: "image".to_string(),
+                subtype: None
+            }
+        );
+        assert_eq!(attachment_type("video"), attachment_type("video/*"));
+        assert_eq!(
+            attachment_type(".PNG"),
+            AttachmentType::Extension("png".to_string())
+        );
+        assert_eq!(attachment_type(".tar.gz").to_string(), ".tar.gz");
+        assert_eq!(attachment_type("audio").to_string(), "audio/*");
+
+        for invalid in [
+            "png",
+            "text",
+            "image/",
+            "/png",
+            "image/p ng",
+            ".",
+            "*/*",
+            "",
+        ] {
+            assert!(invalid.parse::<AttachmentType>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn mime_types_match_content_type() {
+        let png = attachment("cat.png", Some("image/png"));
+        let utf8 = attachment("notes.txt", Some("text/plain; charset=utf-8"));
+
+        assert!(attachment_type("image/png").matches(&png));
+        assert!(attachment_type("image/*").matches(&png));
+        assert!(attachment_type("image").matches(&png));
+        assert!(!attachment_type("image/gif").matches(&png));
+        assert!(!attachment_type("video").matches(&png));
+        assert!(attachment_type("text/plain").matches(&utf8));
+        assert!(attachment_type("IMAGE/PNG").matches(&attachment("cat.png", Some("Image/PNG"))));
+        // Discord doesn't always know the type, the file name doesn't stand in for it
+        assert!(!attachment_type("image/*").matches(&attachment("cat.png", None)));
+    }
+
+    #[test]
+    fn extensions_match_file_name() {
+        let png = attachment("Cat.PNG", None);
+
+        assert!(attachment_type(".png").matches(&png));
+        assert!(!attachment_type(".jpg").matches(&png));
+        assert!(attachment_type(".gz").matches(&attachment("logs.tar.gz", None)));
+        assert!(!attachment_type(".png").matches(&attachment("png", None)));
+        assert!(!attachment_type(".png").matches(&attachment("README", None)));
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn allowing(domains: &[&str]) -> DomainFilter {
+        DomainFilter {
+            allow: domains.iter().map(|domain| domain.to_string()).collect(),
+            deny: Vec::new(),
+        }
+    }
+
+    fn denying(domains: &[&str]) -> DomainFilter {
+        DomainFilter {
+            allow: Vec::new(),
+            deny: domains.iter().map(|domain| domain.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn domain_covers_its_subdomains() {
+        let filter = allowing(&["youtube.com"]);
+        assert!(filter.allows(&url("https://youtube.com/watch?v=1")));
+        assert!(filter.allows(&url("https://www.youtube.com/watch?v=1")));
+        assert!(filter.allows(&url("https://m.www.youtube.com/")));
+        assert!(!filter.allows(&url("https://youtu.be/1")));
+
+        let filter = denying(&["youtube.com"]);
+        assert!(!filter.allows(&url("https://www.youtube.com/")));
+        assert!(filter.allows(&url("https://vimeo.com/1")));
+    }
+
+    #[test]
+    fn domain_does_not_cover_lookalikes() {
+        let filter = allowing(&["youtube.com"]);
+        assert!(!filter.allows(&url("https://evilyoutube.com/")));
+        assert!(!filter.allows(&url("https://youtube.com.evil.net/")));
+
+        let filter = denying(&["youtube.com"]);
+        assert!(filter.allows(&url("https://evilyoutube.com/")));
+    }
+
+    #[test]
+    fn trailing_dots_are_ignored() {
+        assert!(allowing(&["youtube.com"]).allows(&url("https://youtube.com./watch")));
+        assert!(!denying(&["youtube.com"]).allows(&url("https://www.YouTube.com./")));
+        assert_eq!(
+            parse_domain("youtube.com."),
+            Some("youtube.com".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_domain_normalizes() {
+        assert_eq!(parse_domain("YouTube.com"), Some("youtube.com".to_string()));
+        assert_eq!(
+            parse_domain("*.youtube.com"),
+            Some("youtube.com".to_string())
+        );
+        assert_eq!(
+            parse_domain("https://www.youtube.com/watch?v=1"),
+            Some("www.youtube.com".to_string())
+        );
+        assert_eq!(
+            parse_domain("<https://youtube.com/>"),
+            Some("youtube.com".to_string())
+        );
+        assert_eq!(parse_domain("localhost"), None);
+        assert_eq!(parse_domain("you tube.com"), None);
+        assert_eq!(parse_domain("youtube..com"), None);
+    }
+
+    #[test]
+    fn links_are_found_inside_wrapping() {
+        let host = |word: &str| link_in_word(word).map(|url| url.host_str().unwrap().to_string());
+        let youtube = Some("youtube.com".to_string());
+
+        assert_eq!(host("https://youtube.com/watch"), youtube);
+        assert_eq!(host("<https://youtube.com/watch>"), youtube);
+        assert_eq!(host("[clip](https://youtube.com/watch)"), youtube);
+        assert_eq!(host("||https://youtube.com/watch||"), youtube);
+        assert_eq!(host("**<https://youtube.com/watch>**"), youtube);
+        assert_eq!(host("https://youtube.com/watch."), youtube);
+        assert_eq!(host("youtube.com/watch"), None);
+        assert_eq!(host("ftp://youtube.com/file"), None);
+    }
+
+    #[test]
+    fn wrapped_link_is_checked_against_the_filter() {
+        let filter = allowing(&["youtube.com"]);
+        let allowed = |content: &str| links(content).iter().any(|url| filter.allows(url));
+
+        assert!(allowed("look <https://www.youtube.com/watch?v=1>"));
+        assert!(allowed("[clip](https://youtube.com/watch?v=1)"));
+        assert!(!allowed("<https://evilyoutube.com/watch?v=1>"));
+        assert_eq!(caption_length("nice <https://youtube.com/watch?v=1>"), 4);
+    }
+
+    #[test]
+    fn only_starter_rules_tell_starters_apart() {
+        let rules = |threads, thread_text| ChannelRules {
+            policy: ChannelPolicy {
+                threads,
+                thread_text,
+                ..ChannelPolicy::default()
+            },
+            ..ChannelRules::default()
+        };
+
+        assert!(rules(ThreadMode::Starter, false).tells_starter_apart());
+        assert!(rules(ThreadMode::Enforce, true).tells_starter_apart());
+        assert!(!rules(ThreadMode::Enforce, false).tells_starter_apart());
+        assert!(!rules(ThreadMode::Ignore, true).tells_starter_apart());
+    }
+}
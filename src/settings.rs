@@ -0,0 +1,1287 @@
+use serenity::cache::Cache;
+use serenity::client::Context;
+use serenity::model::channel::ChannelType;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::prelude::{RwLock, TypeMapKey};
+use sqlite::Value;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::error::Result;
+use crate::exemption::Exemption;
+use crate::migrations;
+use crate::modlog::{Deletion, DeletionFilter};
+use crate::notice::{NoticeConfig, NoticeRemoval};
+use crate::penalty::{EscalationStep, Penalty};
+use crate::policy::{AttachmentType, ChannelPolicy, ChannelRules, DomainFilter, ThreadMode};
+
+/// Tables that were keyed by channel alone before settings were kept per guild, and their
+/// channel column. Old rows get their guild when the guild they belong to is loaded, and are
+/// deleted when no guild the bot is in claims them.
+const CHANNEL_TABLES: [(&str, &str); 5] = [
+    ("banned_channels", "channel_id"),
+    ("enforced_categories", "category_id"),
+    ("excluded_channels", "channel_id"),
+    ("channel_policies", "channel_id"),
+    ("attachment_types", "channel_id"),
+];
+
+/// Every table with a `guild_id` column, and its channel column if settings in it belong to a channel.
+const GUILD_TABLES: [(&str, Option<&str>); 12] = [
+    ("banned_channels", Some("channel_id")),
+    ("enforced_categories", Some("category_id")),
+    ("excluded_channels", Some("channel_id")),
+    ("channel_policies", Some("channel_id")),
+    ("attachment_types", Some("channel_id")),
+    ("domain_filters", Some("channel_id")),
+    ("notice_configs", Some("channel_id")),
+    ("exemptions", Some("channel_id")),
+    ("guild_configs", None),
+    ("escalation_steps", None),
+    // Removed messages stay in the log when their channel is deleted
+    ("deletions", None),
+    // Notices go with their channel, and can't be removed once the bot left the guild
+    ("notice_removals", Some("channel_id")),
+];
+
+/// Where a setting applies: a whole guild, or one of its channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Guild(GuildId),
+    Channel(GuildId, ChannelId),
+}
+
+impl Scope {
+    fn guild_id(self) -> GuildId {
+        match self {
+            Scope::Guild(guild_id) | Scope::Channel(guild_id, _) => guild_id,
+        }
+    }
+
+    fn channel_id(self) -> Option<ChannelId> {
+        match self {
+            Scope::Guild(_) => None,
+            Scope::Channel(_, channel_id) => Some(channel_id),
+        }
+    }
+}
+
+/// How long to wait for another process to finish writing, in milliseconds
+const BUSY_TIMEOUT: usize = 5000;
+
+/// The command prefix of guilds that didn't choose their own, and in DMs
+pub const DEFAULT_PREFIX: &str = "~";
+
+/// Settings that apply to a whole guild.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuildConfig {
+    /// DM authors a copy of their removed message instead of posting a notice
+    pub dm_offenders: bool,
+    /// Channel every removed message is logged to
+    pub log_channel: Option<ChannelId>,
+    /// Violations older than this many seconds don't count towards penalties
+    pub escalation_window: u64,
+    /// Prefix for commands, mentioning the bot works as well
+    pub prefix: String,
+}
+
+impl Default for GuildConfig {
+    fn default() -> Self {
+        GuildConfig {
+            dm_offenders: false,
+            log_channel: None,
+            escalation_window: 24 * 60 * 60,
+            prefix: DEFAULT_PREFIX.to_string(),
+        }
+    }
+}
+
+/// Everything configured in one guild. Scoped settings are keyed by `None` for the guild's own.
+#[derive(Debug, Default)]
+struct GuildSettings {
+    config: GuildConfig,
+    banned_channels: HashSet<ChannelId>,
+    enforced_categories: HashSet<ChannelId>,
+    /// Channels that opted out of their category's enforcement
+    excluded_channels: HashSet<ChannelId>,
+    channel_policies: HashMap<ChannelId, ChannelPolicy>,
+    attachment_types: HashMap<ChannelId, Vec<AttachmentType>>,
+    domain_filters: HashMap<Option<ChannelId>, DomainFilter>,
+    notice_configs: HashMap<Option<ChannelId>, NoticeConfig>,
+    /// Ordered by violation count
+    escalation_steps: Vec<EscalationStep>,
+    exemptions: HashMap<Option<ChannelId>, Vec<Exemption>>,
+}
+
+/// The settings of every available guild, loaded by [`Settings::load_guild`] when the guild
+/// becomes available.
+pub struct Settings {
+    connection: Mutex<sqlite::Connection>,
+
+    guilds: HashMap<GuildId, GuildSettings>,
+}
+
+impl Settings {
+    /// Open the database and bring its schema up to date.
+    ///
+    /// Fails if the database was migrated by a newer build.
+    pub fn load() -> Result<Self> {
+        fs::create_dir_all("data")?;
+
+        let mut connection = sqlite::open("data/settings.sqlite")?;
+        // Processes running different shards share the database, wait for each other's writes
+        // instead of failing, and let reads go on during a write
+        connection.set_busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute("PRAGMA journal_mode = WAL")?;
+        migrations::migrate(&connection)?;
+
+        Ok(Settings {
+            connection: Mutex::new(connection),
+            guilds: HashMap::new(),
+        })
+    }
+
+    /// Write everything to the main database file, before the process exits.
+    pub fn close(&self) -> Result<()> {
+        lock(&self.connection).execute("PRAGMA wal_checkpoint(TRUNCATE)")?;
+        Ok(())
+    }
+
+    /// Read a guild's settings from the database, replacing any loaded before.
+    ///
+    /// `channels` are all of the guild's channels, categories and threads. Rows stored before
+    /// settings were kept per guild are claimed for the guild through them.
+    pub fn load_guild(&mut self, guild: GuildId, channels: &[ChannelId]) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let guild_value = [Value::Integer(guild.0 as i64)];
+        let mut settings = GuildSettings::default();
+
+        claim_rows(&conn_lock, guild, channels)?;
+
+        // Load guild_configs
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT dm_offenders, log_channel, escalation_window, prefix FROM guild_configs WHERE guild_id = ?")
+                ?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            if let Some(row) = cursor.next()? {
+                settings.config = GuildConfig {
+                    dm_offenders: row[0].as_integer().unwrap_or(0) != 0,
+                    log_channel: row[1]
+                        .as_integer()
+                        .map(|channel_id| ChannelId(channel_id as u64)),
+                    escalation_window: row[2].as_integer().unwrap_or(0) as u64,
+                    prefix: row[3].as_string().unwrap_or(DEFAULT_PREFIX).to_string(),
+                };
+            }
+        }
+
+        // Load banned_channels
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT channel_id FROM banned_channels WHERE guild_id = ?")?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                if let Value::Integer(channel_id) = row[0] {
+                    settings
+                        .banned_channels
+                        .insert(ChannelId(channel_id as u64));
+                }
+            }
+        }
+
+        // Load enforced_categories
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT category_id FROM enforced_categories WHERE guild_id = ?")?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                if let Value::Integer(category_id) = row[0] {
+                    settings
+                        .enforced_categories
+                        .insert(ChannelId(category_id as u64));
+                }
+            }
+        }
+
+        // Load excluded_channels
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT channel_id FROM excluded_channels WHERE guild_id = ?")?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                if let Value::Integer(channel_id) = row[0] {
+                    settings
+                        .excluded_channels
+                        .insert(ChannelId(channel_id as u64));
+                }
+            }
+        }
+
+        // Load channel_policies
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT channel_id, links, attachments, embeds, stickers, thread_text, max_caption, threads, edits FROM channel_policies WHERE guild_id = ?")
+                ?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                let flag = |i: usize| row[i].as_integer().unwrap_or(0) != 0;
+                if let Value::Integer(channel_id) = row[0] {
+                    let policy = ChannelPolicy {
+                        links: flag(1),
+                        attachments: flag(2),
+                        embeds: flag(3),
+                        stickers: flag(4),
+                        thread_text: flag(5),
+                        max_caption: row[6].as_integer().map(|limit| limit as u32),
+                        threads: row[7]
+                            .as_string()
+                            .and_then(|threads| threads.parse().ok())
+                            .unwrap_or(ThreadMode::Enforce),
+                        edits: flag(8),
+                    };
+                    settings
+                        .channel_policies
+                        .insert(ChannelId(channel_id as u64), policy);
+                }
+            }
+        }
+
+        // Load attachment_types
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT channel_id, type FROM attachment_types WHERE guild_id = ?")?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                if let (Value::Integer(channel_id), Value::String(ty)) = (&row[0], &row[1]) {
+                    if let Ok(ty) = ty.parse() {
+                        settings
+                            .attachment_types
+                            .entry(ChannelId(*channel_id as u64))
+                            .or_default()
+                            .push(ty);
+                    }
+                }
+            }
+        }
+
+        // Load domain_filters
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT channel_id, domain, allow FROM domain_filters WHERE guild_id = ?")?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                if let (Value::String(domain), Value::Integer(allow)) = (&row[1], &row[2]) {
+                    let channel = row[0].as_integer().map(|c| ChannelId(c as u64));
+                    let filter = settings.domain_filters.entry(channel).or_default();
+                    if *allow != 0 {
+                        filter.allow.push(domain.clone());
+                    } else {
+                        filter.deny.push(domain.clone());
+                    }
+                }
+            }
+        }
+
+        // Load notice_configs
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT channel_id, enabled, template, lifetime, embed, coalesce_window, cooldown FROM notice_configs WHERE guild_id = ?")
+                ?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                if let Value::String(template) = &row[2] {
+                    let channel = row[0].as_integer().map(|c| ChannelId(c as u64));
+                    let config = NoticeConfig {
+                        enabled: row[1].as_integer().unwrap_or(1) != 0,
+                        template: template.clone(),
+                        lifetime: row[3].as_integer().map(|lifetime| lifetime as u64),
+                        embed: row[4].as_integer().unwrap_or(0) != 0,
+                        coalesce_window: row[5].as_integer().unwrap_or(0) as u64,
+                        cooldown: row[6].as_integer().unwrap_or(0) as u64,
+                    };
+                    settings.notice_configs.insert(channel, config);
+                }
+            }
+        }
+
+        // Load escalation_steps
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT violations, kind, value FROM escalation_steps WHERE guild_id = ? ORDER BY violations")
+                ?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                if let (Value::Integer(violations), Value::String(kind)) = (&row[0], &row[1]) {
+                    if let Some(penalty) = Penalty::from_row(kind, row[2].as_integer()) {
+                        settings.escalation_steps.push(EscalationStep {
+                            violations: *violations as u32,
+                            penalty,
+                        });
+                    }
+                }
+            }
+        }
+
+        // Load exemptions
+        {
+            let mut cursor = conn_lock
+                .prepare("SELECT channel_id, kind, value FROM exemptions WHERE guild_id = ?")?
+                .into_cursor();
+            cursor.bind(&guild_value)?;
+
+            while let Some(row) = cursor.next()? {
+                if let (Value::String(kind), Value::Integer(value)) = (&row[1], &row[2]) {
+                    let channel = row[0].as_integer().map(|c| ChannelId(c as u64));
+                    if let Some(exemption) = Exemption::from_row(kind, *value) {
+                        settings
+                            .exemptions
+                            .entry(channel)
+                            .or_default()
+                            .push(exemption);
+                    }
+                }
+            }
+        }
+
+        self.guilds.insert(guild, settings);
+        Ok(())
+    }
+
+    /// Delete the rows stored before settings were kept per guild that no guild claimed, which
+    /// belong to guilds the bot left while it wasn't running. Returns how many were deleted.
+    ///
+    /// `guilds` are every guild the bot is in with their channels. Their rows are claimed first,
+    /// in case one of them is still being loaded.
+    pub fn delete_unclaimed(&self, guilds: &[(GuildId, Vec<ChannelId>)]) -> Result<usize> {
+        let conn_lock = lock(&self.connection);
+        for (guild, channels) in guilds {
+            claim_rows(&conn_lock, *guild, channels)?;
+        }
+
+        transaction(&conn_lock, || {
+            let mut deleted = 0;
+            for (table, _) in CHANNEL_TABLES {
+                conn_lock.execute(format!("DELETE FROM {} WHERE guild_id IS NULL", table))?;
+                deleted += conn_lock.change_count();
+            }
+            Ok(deleted)
+        })
+    }
+
+    /// Forget a guild's settings without touching the database, for when it becomes unavailable.
+    pub fn unload_guild(&mut self, guild: GuildId) {
+        self.guilds.remove(&guild);
+    }
+
+    /// Delete everything stored for a guild, for when the bot leaves it.
+    pub fn delete_guild(&mut self, guild: GuildId) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        for (table, _) in GUILD_TABLES {
+            let mut statement =
+                conn_lock.prepare(format!("DELETE FROM {} WHERE guild_id = ?", table))?;
+            statement.bind(1, guild.0 as i64)?;
+            statement.next()?;
+        }
+
+        self.guilds.remove(&guild);
+        Ok(())
+    }
+
+    /// Delete the settings of a channel, category or thread that was deleted.
+    pub fn delete_channel(&mut self, guild: GuildId, channel: ChannelId) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        for (table, column) in GUILD_TABLES {
+            let column = match column {
+                Some(column) => column,
+                None => continue,
+            };
+            let mut statement = conn_lock.prepare(format!(
+                "DELETE FROM {} WHERE guild_id = ? AND {} = ?",
+                table, column
+            ))?;
+            statement.bind(1, guild.0 as i64)?;
+            statement.bind(2, channel.0 as i64)?;
+            statement.next()?;
+        }
+
+        if let Some(settings) = self.guilds.get_mut(&guild) {
+            settings.banned_channels.remove(&channel);
+            settings.enforced_categories.remove(&channel);
+            settings.excluded_channels.remove(&channel);
+            settings.channel_policies.remove(&channel);
+            settings.attachment_types.remove(&channel);
+            settings.domain_filters.remove(&Some(channel));
+            settings.notice_configs.remove(&Some(channel));
+            settings.exemptions.remove(&Some(channel));
+        }
+        Ok(())
+    }
+
+    /// Whether a channel was toggled on by itself, rather than through its category.
+    pub fn is_channel_toggled(&self, guild: GuildId, channel: ChannelId) -> bool {
+        matches!(self.guilds.get(&guild), Some(settings) if settings.banned_channels.contains(&channel))
+    }
+
+    /// Whether messages in a channel are checked, either on its own or through its category.
+    pub fn is_enforced(
+        &self,
+        guild: GuildId,
+        channel: ChannelId,
+        category: Option<ChannelId>,
+    ) -> bool {
+        self.is_channel_toggled(guild, channel)
+            || matches!(category, Some(category) if self.is_category_enforced(guild, category)
+                && !self.guilds[&guild].excluded_channels.contains(&channel))
+    }
+
+    /// Enforce a channel or stop enforcing it, opting it out of or back into its category's
+    /// enforcement when the category is enforced. Returns whether it was enforced before.
+    pub fn set_channel_enforced(
+        &mut self,
+        guild: GuildId,
+        channel: ChannelId,
+        category: Option<ChannelId>,
+        enforced: bool,
+    ) -> Result<bool> {
+        let was_enforced = self.is_enforced(guild, channel, category);
+        if was_enforced == enforced {
+            return Ok(was_enforced);
+        }
+        let category_enforced =
+            matches!(category, Some(category) if self.is_category_enforced(guild, category));
+
+        let settings = self.guilds.entry(guild).or_default();
+        let conn_lock = lock(&self.connection);
+        let update = |sql: &str| -> Result<()> {
+            let mut statement = conn_lock.prepare(sql)?;
+            statement.bind(1, channel.0 as i64)?;
+            statement.bind(2, guild.0 as i64)?;
+            statement.next()?;
+            Ok(())
+        };
+        if was_enforced {
+            if settings.banned_channels.contains(&channel) {
+                update("DELETE FROM banned_channels WHERE channel_id = ? AND guild_id = ?")?;
+                settings.banned_channels.remove(&channel);
+            }
+            if category_enforced && !settings.excluded_channels.contains(&channel) {
+                update("INSERT INTO excluded_channels (channel_id, guild_id) VALUES (?, ?)")?;
+                settings.excluded_channels.insert(channel);
+            }
+        } else if category_enforced {
+            update("DELETE FROM excluded_channels WHERE channel_id = ? AND guild_id = ?")?;
+            settings.excluded_channels.remove(&channel);
+        } else {
+            update("INSERT INTO banned_channels (channel_id, guild_id) VALUES (?, ?)")?;
+            settings.banned_channels.insert(channel);
+        }
+        Ok(was_enforced)
+    }
+
+    pub fn is_category_enforced(&self, guild: GuildId, category: ChannelId) -> bool {
+        matches!(self.guilds.get(&guild), Some(settings) if settings.enforced_categories.contains(&category))
+    }
+
+    /// Start or stop enforcing every channel in a category. Returns whether it was enforced before.
+    pub fn toggle_category(&mut self, guild: GuildId, category: ChannelId) -> Result<bool> {
+        let was_enforced = self.is_category_enforced(guild, category);
+        self.set_category_enforced(guild, category, !was_enforced)
+    }
+
+    /// Returns whether the category was enforced before.
+    pub fn set_category_enforced(
+        &mut self,
+        guild: GuildId,
+        category: ChannelId,
+        enforced: bool,
+    ) -> Result<bool> {
+        let was_enforced = self.is_category_enforced(guild, category);
+        if was_enforced == enforced {
+            return Ok(was_enforced);
+        }
+
+        let sql = if enforced {
+            "INSERT INTO enforced_categories (category_id, guild_id) VALUES (?, ?)"
+        } else {
+            "DELETE FROM enforced_categories WHERE category_id = ? AND guild_id = ?"
+        };
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare(sql)?;
+        statement.bind(1, category.0 as i64)?;
+        statement.bind(2, guild.0 as i64)?;
+        statement.next()?;
+
+        let categories = &mut self.guilds.entry(guild).or_default().enforced_categories;
+        if enforced {
+            categories.insert(category);
+        } else {
+            categories.remove(&category);
+        }
+        Ok(was_enforced)
+    }
+
+    /// The policy for a channel: its own, its category's, or the default if neither was edited.
+    pub fn policy(
+        &self,
+        guild: GuildId,
+        channel: ChannelId,
+        category: Option<ChannelId>,
+    ) -> ChannelPolicy {
+        let policies = match self.guilds.get(&guild) {
+            Some(settings) => &settings.channel_policies,
+            None => return ChannelPolicy::default(),
+        };
+        policies
+            .get(&channel)
+            .or_else(|| policies.get(&category?))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Whether a channel has its own policy rather than following its category's.
+    pub fn has_policy_override(&self, guild: GuildId, channel: ChannelId) -> bool {
+        matches!(self.guilds.get(&guild), Some(settings) if settings.channel_policies.contains_key(&channel))
+    }
+
+    pub fn set_policy(
+        &mut self,
+        guild: GuildId,
+        channel: ChannelId,
+        policy: ChannelPolicy,
+    ) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock
+            .prepare(
+                "INSERT OR REPLACE INTO channel_policies \
+                (channel_id, links, attachments, embeds, stickers, thread_text, max_caption, threads, edits, guild_id) \
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            ?;
+        statement.bind(1, channel.0 as i64)?;
+        statement.bind(2, policy.links as i64)?;
+        statement.bind(3, policy.attachments as i64)?;
+        statement.bind(4, policy.embeds as i64)?;
+        statement.bind(5, policy.stickers as i64)?;
+        statement.bind(6, policy.thread_text as i64)?;
+        statement.bind(7, policy.max_caption.map(|limit| limit as i64))?;
+        statement.bind(8, policy.threads.name())?;
+        statement.bind(9, policy.edits as i64)?;
+        statement.bind(10, guild.0 as i64)?;
+        statement.next()?;
+
+        self.guilds
+            .entry(guild)
+            .or_default()
+            .channel_policies
+            .insert(channel, policy);
+        Ok(())
+    }
+
+    /// The attachment types that count as media in a channel; empty means any attachment.
+    ///
+    /// A channel without a list of its own uses its category's.
+    pub fn attachment_types(
+        &self,
+        guild: GuildId,
+        channel: ChannelId,
+        category: Option<ChannelId>,
+    ) -> &[AttachmentType] {
+        let own = |channel| {
+            self.guilds
+                .get(&guild)?
+                .attachment_types
+                .get(&channel)
+                .filter(|types| !types.is_empty())
+        };
+        own(channel)
+            .or_else(|| own(category?))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns false if the type was already allowed.
+    pub fn add_attachment_type(
+        &mut self,
+        guild: GuildId,
+        channel: ChannelId,
+        ty: AttachmentType,
+    ) -> Result<bool> {
+        let types = self
+            .guilds
+            .entry(guild)
+            .or_default()
+            .attachment_types
+            .entry(channel)
+            .or_default();
+        if types.contains(&ty) {
+            return Ok(false);
+        }
+
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare(
+            "INSERT INTO attachment_types (channel_id, type, guild_id) VALUES (?, ?, ?)",
+        )?;
+        statement.bind(1, channel.0 as i64)?;
+        statement.bind(2, ty.to_string().as_str())?;
+        statement.bind(3, guild.0 as i64)?;
+        statement.next()?;
+
+        types.push(ty);
+        Ok(true)
+    }
+
+    /// Returns false if the type wasn't allowed in the first place.
+    pub fn remove_attachment_type(
+        &mut self,
+        guild: GuildId,
+        channel: ChannelId,
+        ty: &AttachmentType,
+    ) -> Result<bool> {
+        let types = self
+            .guilds
+            .entry(guild)
+            .or_default()
+            .attachment_types
+            .entry(channel)
+            .or_default();
+        if !types.contains(ty) {
+            return Ok(false);
+        }
+
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare(
+            "DELETE FROM attachment_types WHERE guild_id = ? AND channel_id = ? AND type = ?",
+        )?;
+        statement.bind(1, guild.0 as i64)?;
+        statement.bind(2, channel.0 as i64)?;
+        statement.bind(3, ty.to_string().as_str())?;
+        statement.next()?;
+
+        types.retain(|other| other != ty);
+        Ok(true)
+    }
+
+    pub fn clear_attachment_types(&mut self, guild: GuildId, channel: ChannelId) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock
+            .prepare("DELETE FROM attachment_types WHERE guild_id = ? AND channel_id = ?")?;
+        statement.bind(1, guild.0 as i64)?;
+        statement.bind(2, channel.0 as i64)?;
+        statement.next()?;
+
+        if let Some(settings) = self.guilds.get_mut(&guild) {
+            settings.attachment_types.remove(&channel);
+        }
+        Ok(())
+    }
+
+    /// The domain lists configured at exactly this scope.
+    pub fn domain_filter(&self, scope: Scope) -> DomainFilter {
+        self.guilds
+            .get(&scope.guild_id())
+            .and_then(|settings| settings.domain_filters.get(&scope.channel_id()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Add a domain to the allow or deny list of a scope, moving it if it
+    /// was on the other list. Returns false if nothing changed.
+    pub fn add_domain(&mut self, scope: Scope, domain: String, allow: bool) -> Result<bool> {
+        let filter = self
+            .guilds
+            .entry(scope.guild_id())
+            .or_default()
+            .domain_filters
+            .entry(scope.channel_id())
+            .or_default();
+        let (list, other) = if allow {
+            (&mut filter.allow, &mut filter.deny)
+        } else {
+            (&mut filter.deny, &mut filter.allow)
+        };
+        if list.contains(&domain) {
+            return Ok(false);
+        }
+
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare(
+            "DELETE FROM domain_filters WHERE guild_id = ? AND channel_id IS ? AND domain = ?",
+        )?;
+        statement.bind(1, scope.guild_id().0 as i64)?;
+        statement.bind(2, scope.channel_id().map(|c| c.0 as i64))?;
+        statement.bind(3, domain.as_str())?;
+        statement.next()?;
+
+        let mut statement = conn_lock.prepare("INSERT INTO domain_filters VALUES (?, ?, ?, ?)")?;
+        statement.bind(1, scope.guild_id().0 as i64)?;
+        statement.bind(2, scope.channel_id().map(|c| c.0 as i64))?;
+        statement.bind(3, domain.as_str())?;
+        statement.bind(4, allow as i64)?;
+        statement.next()?;
+
+        other.retain(|other| *other != domain);
+        list.push(domain);
+        Ok(true)
+    }
+
+    /// Returns false if the domain wasn't on either list.
+    pub fn remove_domain(&mut self, scope: Scope, domain: &str) -> Result<bool> {
+        let filter = self
+            .guilds
+            .entry(scope.guild_id())
+            .or_default()
+            .domain_filters
+            .entry(scope.channel_id())
+            .or_default();
+        if !filter.allow.iter().chain(&filter.deny).any(|d| d == domain) {
+            return Ok(false);
+        }
+
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare(
+            "DELETE FROM domain_filters WHERE guild_id = ? AND channel_id IS ? AND domain = ?",
+        )?;
+        statement.bind(1, scope.guild_id().0 as i64)?;
+        statement.bind(2, scope.channel_id().map(|c| c.0 as i64))?;
+        statement.bind(3, domain)?;
+        statement.next()?;
+
+        filter.allow.retain(|d| d != domain);
+        filter.deny.retain(|d| d != domain);
+        Ok(true)
+    }
+
+    /// The notice config that applies in a channel: its own override, the guild's, or the default.
+    pub fn notice_config(&self, scope: Scope) -> NoticeConfig {
+        self.guilds
+            .get(&scope.guild_id())
+            .and_then(|settings| {
+                settings
+                    .notice_configs
+                    .get(&scope.channel_id())
+                    .or_else(|| settings.notice_configs.get(&None))
+            })
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Whether a channel overrides the guild's notice config.
+    pub fn has_notice_override(&self, scope: Scope) -> bool {
+        matches!(scope, Scope::Channel(..))
+            && matches!(self.guilds.get(&scope.guild_id()), Some(settings)
+                if settings.notice_configs.contains_key(&scope.channel_id()))
+    }
+
+    pub fn set_notice_config(&mut self, scope: Scope, config: NoticeConfig) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock
+            .prepare("DELETE FROM notice_configs WHERE guild_id = ? AND channel_id IS ?")?;
+        statement.bind(1, scope.guild_id().0 as i64)?;
+        statement.bind(2, scope.channel_id().map(|c| c.0 as i64))?;
+        statement.next()?;
+
+        let mut statement =
+            conn_lock.prepare("INSERT INTO notice_configs VALUES (?, ?, ?, ?, ?, ?, ?, ?)")?;
+        statement.bind(1, scope.guild_id().0 as i64)?;
+        statement.bind(2, scope.channel_id().map(|c| c.0 as i64))?;
+        statement.bind(3, config.enabled as i64)?;
+        statement.bind(4, config.template.as_str())?;
+        statement.bind(5, config.lifetime.map(|lifetime| lifetime as i64))?;
+        statement.bind(6, config.embed as i64)?;
+        statement.bind(7, config.coalesce_window as i64)?;
+        statement.bind(8, config.cooldown as i64)?;
+        statement.next()?;
+
+        self.guilds
+            .entry(scope.guild_id())
+            .or_default()
+            .notice_configs
+            .insert(scope.channel_id(), config);
+        Ok(())
+    }
+
+    /// Drop the config set at exactly this scope, falling back to the guild's or the default.
+    pub fn reset_notice_config(&mut self, scope: Scope) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock
+            .prepare("DELETE FROM notice_configs WHERE guild_id = ? AND channel_id IS ?")?;
+        statement.bind(1, scope.guild_id().0 as i64)?;
+        statement.bind(2, scope.channel_id().map(|c| c.0 as i64))?;
+        statement.next()?;
+
+        if let Some(settings) = self.guilds.get_mut(&scope.guild_id()) {
+            settings.notice_configs.remove(&scope.channel_id());
+        }
+        Ok(())
+    }
+
+    pub fn guild_config(&self, guild: GuildId) -> GuildConfig {
+        self.guilds
+            .get(&guild)
+            .map(|settings| settings.config.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_guild_config(&mut self, guild: GuildId, config: GuildConfig) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let mut statement =
+            conn_lock.prepare("INSERT OR REPLACE INTO guild_configs VALUES (?, ?, ?, ?, ?)")?;
+        statement.bind(1, guild.0 as i64)?;
+        statement.bind(2, config.dm_offenders as i64)?;
+        statement.bind(3, config.log_channel.map(|channel| channel.0 as i64))?;
+        statement.bind(4, config.escalation_window as i64)?;
+        statement.bind(5, config.prefix.as_str())?;
+        statement.next()?;
+
+        self.guilds.entry(guild).or_default().config = config;
+        Ok(())
+    }
+
+    pub fn record_deletion(&self, deletion: &Deletion) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let mut statement =
+            conn_lock.prepare("INSERT INTO deletions VALUES (?, ?, ?, ?, ?, ?, ?, ?)")?;
+        statement.bind(1, deletion.guild_id.0 as i64)?;
+        statement.bind(2, deletion.channel_id.0 as i64)?;
+        statement.bind(3, deletion.user_id.0 as i64)?;
+        statement.bind(4, deletion.message_id.0 as i64)?;
+        statement.bind(5, deletion.content.as_str())?;
+        statement.bind(6, deletion.rule.as_str())?;
+        statement.bind(7, deletion.reason.as_str())?;
+        statement.bind(8, deletion.deleted_at)?;
+        statement.next()?;
+        Ok(())
+    }
+
+    /// Remember to remove a notice once it's due, replacing an earlier due time.
+    pub fn queue_notice_removal(&self, removal: &NoticeRemoval) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let mut statement =
+            conn_lock.prepare("INSERT OR REPLACE INTO notice_removals VALUES (?, ?, ?, ?)")?;
+        statement.bind(1, removal.guild_id.0 as i64)?;
+        statement.bind(2, removal.channel_id.0 as i64)?;
+        statement.bind(3, removal.message_id.0 as i64)?;
+        statement.bind(4, removal.due_at)?;
+        statement.next()?;
+        Ok(())
+    }
+
+    /// Notices due for removal by a unix time, soonest first.
+    ///
+    /// Only notices in loaded guilds, other processes sharing the database take care of theirs.
+    pub fn due_notice_removals(&self, now: i64) -> Result<Vec<NoticeRemoval>> {
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare(
+            "SELECT guild_id, channel_id, message_id, due_at FROM notice_removals \
+                WHERE due_at <= ? ORDER BY due_at",
+        )?;
+        statement.bind(1, now)?;
+
+        let mut removals = Vec::new();
+        while let sqlite::State::Row = statement.next()? {
+            let removal = NoticeRemoval {
+                guild_id: GuildId(statement.read::<i64>(0)? as u64),
+                channel_id: ChannelId(statement.read::<i64>(1)? as u64),
+                message_id: MessageId(statement.read::<i64>(2)? as u64),
+                due_at: statement.read(3)?,
+            };
+            if self.guilds.contains_key(&removal.guild_id) {
+                removals.push(removal);
+            }
+        }
+        Ok(removals)
+    }
+
+    /// Forget a notice that was removed, or can't be anymore.
+    pub fn dequeue_notice_removal(&self, message: MessageId) -> Result<()> {
+        let conn_lock = lock(&self.connection);
+        let mut statement =
+            conn_lock.prepare("DELETE FROM notice_removals WHERE message_id = ?")?;
+        statement.bind(1, message.0 as i64)?;
+        statement.next()?;
+        Ok(())
+    }
+
+    /// The most recent deletions in a guild, newest first.
+    pub fn deletions(
+        &self,
+        guild: GuildId,
+        filter: DeletionFilter,
+        limit: usize,
+    ) -> Result<Vec<Deletion>> {
+        let (condition, id) = match filter {
+            DeletionFilter::All => ("", None),
+            DeletionFilter::User(user) => (" AND user_id = ?", Some(user.0)),
+            DeletionFilter::Channel(channel) => (" AND channel_id = ?", Some(channel.0)),
+        };
+
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare(format!(
+            "SELECT guild_id, channel_id, user_id, message_id, content, rule, reason, deleted_at \
+                FROM deletions WHERE guild_id = ?{} ORDER BY deleted_at DESC LIMIT {}",
+            condition, limit
+        ))?;
+        statement.bind(1, guild.0 as i64)?;
+        if let Some(id) = id {
+            statement.bind(2, id as i64)?;
+        }
+
+        let mut deletions = Vec::new();
+        while let sqlite::State::Row = statement.next()? {
+            deletions.push(Deletion {
+                guild_id: GuildId(statement.read::<i64>(0)? as u64),
+                channel_id: ChannelId(statement.read::<i64>(1)? as u64),
+                user_id: UserId(statement.read::<i64>(2)? as u64),
+                message_id: MessageId(statement.read::<i64>(3)? as u64),
+                content: statement.read(4)?,
+                rule: statement.read(5)?,
+                reason: statement.read(6)?,
+                deleted_at: statement.read(7)?,
+            });
+        }
+        Ok(deletions)
+    }
+
+    /// How many messages from a member were removed since a unix time.
+    pub fn count_violations(&self, guild: GuildId, user: UserId, since: i64) -> Result<u32> {
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare(
+            "SELECT COUNT(*) FROM deletions WHERE guild_id = ? AND user_id = ? AND deleted_at >= ?",
+        )?;
+        statement.bind(1, guild.0 as i64)?;
+        statement.bind(2, user.0 as i64)?;
+        statement.bind(3, since)?;
+        statement.next()?;
+        Ok(statement.read::<i64>(0)? as u32)
+    }
+
+    /// The guild's escalation steps, ordered by violation count.
+    pub fn escalation_steps(&self, guild: GuildId) -> &[EscalationStep] {
+        self.guilds
+            .get(&guild)
+            .map(|settings| settings.escalation_steps.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Add a step, replacing any step at the same violation count.
+    pub fn set_escalation_step(&mut self, guild: GuildId, step: EscalationStep) -> Result<()> {
+        let (kind, value) = step.penalty.to_row();
+
+        let conn_lock = lock(&self.connection);
+        let mut statement =
+            conn_lock.prepare("INSERT OR REPLACE INTO escalation_steps VALUES (?, ?, ?, ?)")?;
+        statement.bind(1, guild.0 as i64)?;
+        statement.bind(2, step.violations as i64)?;
+        statement.bind(3, kind)?;
+        statement.bind(4, value)?;
+        statement.next()?;
+
+        let steps = &mut self.guilds.entry(guild).or_default().escalation_steps;
+        steps.retain(|other| other.violations != step.violations);
+        steps.push(step);
+        steps.sort_by_key(|step| step.violations);
+        Ok(())
+    }
+
+    /// Returns false if there was no step at this violation count.
+    pub fn remove_escalation_step(&mut self, guild: GuildId, violations: u32) -> Result<bool> {
+        let steps = &mut self.guilds.entry(guild).or_default().escalation_steps;
+        if !steps.iter().any(|step| step.violations == violations) {
+            return Ok(false);
+        }
+
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock
+            .prepare("DELETE FROM escalation_steps WHERE guild_id = ? AND violations = ?")?;
+        statement.bind(1, guild.0 as i64)?;
+        statement.bind(2, violations as i64)?;
+        statement.next()?;
+
+        steps.retain(|step| step.violations != violations);
+        Ok(true)
+    }
+
+    /// The exemptions configured at exactly this scope.
+    pub fn exemptions(&self, scope: Scope) -> &[Exemption] {
+        self.guilds
+            .get(&scope.guild_id())
+            .and_then(|settings| settings.exemptions.get(&scope.channel_id()))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The exemptions that apply in a channel: the guild's and the channel's own.
+    pub fn channel_exemptions(&self, guild: GuildId, channel: ChannelId) -> Vec<Exemption> {
+        let mut exemptions = self.exemptions(Scope::Guild(guild)).to_vec();
+        exemptions.extend_from_slice(self.exemptions(Scope::Channel(guild, channel)));
+        exemptions
+    }
+
+    /// Returns false if the exemption already existed.
+    pub fn add_exemption(&mut self, scope: Scope, exemption: Exemption) -> Result<bool> {
+        let exemptions = self
+            .guilds
+            .entry(scope.guild_id())
+            .or_default()
+            .exemptions
+            .entry(scope.channel_id())
+            .or_default();
+        if exemptions.contains(&exemption) {
+            return Ok(false);
+        }
+
+        let (kind, value) = exemption.to_row();
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock.prepare("INSERT INTO exemptions VALUES (?, ?, ?, ?)")?;
+        statement.bind(1, scope.guild_id().0 as i64)?;
+        statement.bind(2, scope.channel_id().map(|c| c.0 as i64))?;
+        statement.bind(3, kind)?;
+        statement.bind(4, value)?;
+        statement.next()?;
+
+        exemptions.push(exemption);
+        Ok(true)
+    }
+
+    /// Returns false if there was no such exemption.
+    pub fn remove_exemption(&mut self, scope: Scope, exemption: Exemption) -> Result<bool> {
+        let exemptions = self
+            .guilds
+            .entry(scope.guild_id())
+            .or_default()
+            .exemptions
+            .entry(scope.channel_id())
+            .or_default();
+        if !exemptions.contains(&exemption) {
+            return Ok(false);
+        }
+
+        let (kind, value) = exemption.to_row();
+        let conn_lock = lock(&self.connection);
+        let mut statement = conn_lock
+            .prepare(
+                "DELETE FROM exemptions WHERE guild_id = ? AND channel_id IS ? AND kind = ? AND value = ?",
+            )
+            ?;
+        statement.bind(1, scope.guild_id().0 as i64)?;
+        statement.bind(2, scope.channel_id().map(|c| c.0 as i64))?;
+        statement.bind(3, kind)?;
+        statement.bind(4, value)?;
+        statement.next()?;
+
+        exemptions.retain(|other| *other != exemption);
+        Ok(true)
+    }
+
+    /// Everything needed to check a message in a channel.
+    ///
+    /// A channel's own domain allow-list replaces the guild's, while the deny-lists
+    /// of both apply.
+    pub fn channel_rules(
+        &self,
+        guild: GuildId,
+        channel: ChannelId,
+        category: Option<ChannelId>,
+    ) -> ChannelRules {
+        let guild_domains = self.domain_filter(Scope::Guild(guild));
+        let channel_domains = self.domain_filter(Scope::Channel(guild, channel));
+
+        let allow = if channel_domains.allow.is_empty() {
+            guild_domains.allow
+        } else {
+            channel_domains.allow
+        };
+        let mut deny = guild_domains.deny;
+        deny.extend(channel_domains.deny);
+
+        ChannelRules {
+            policy: self.policy(guild, channel, category),
+            attachment_types: self.attachment_types(guild, channel, category).to_vec(),
+            domains: DomainFilter { allow, deny },
+        }
+    }
+}
+
+/// Lock the database connection. A panic while it was locked can't leave it half-written,
+/// SQLite rolls back the statement, so a poisoned lock is still safe to use.
+fn lock(connection: &Mutex<sqlite::Connection>) -> MutexGuard<'_, sqlite::Connection> {
+    connection.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Run statements in a write transaction, which commits them together or not at all.
+fn transaction<T>(
+    connection: &sqlite::Connection,
+    statements: impl FnOnce() -> sqlite::Result<T>,
+) -> Result<T> {
+    connection.execute("BEGIN IMMEDIATE")?;
+    match statements() {
+        Ok(value) => {
+            connection.execute("COMMIT")?;
+            Ok(value)
+        }
+        Err(why) => {
+            connection.execute("ROLLBACK")?;
+            Err(why.into())
+        }
+    }
+}
+
+/// Give rows stored before settings were kept per guild to the guild that has their channel.
+///
+/// Each row is only claimed once, so this skips straight past tables that have none left.
+fn claim_rows(
+    connection: &sqlite::Connection,
+    guild: GuildId,
+    channels: &[ChannelId],
+) -> Result<()> {
+    let mut tables = Vec::new();
+    for (table, column) in CHANNEL_TABLES {
+        let mut cursor = connection
+            .prepare(format!(
+                "SELECT 1 FROM {} WHERE guild_id IS NULL LIMIT 1",
+                table
+            ))?
+            .into_cursor();
+        if cursor.next()?.is_some() {
+            tables.push((table, column));
+        }
+    }
+    if tables.is_empty() {
+        return Ok(());
+    }
+
+    transaction(connection, || {
+        for (table, column) in tables {
+            let mut statement = connection.prepare(format!(
+                "UPDATE {} SET guild_id = ? WHERE guild_id IS NULL AND {} = ?",
+                table, column
+            ))?;
+            for channel in channels {
+                statement.reset()?;
+                statement.bind(1, guild.0 as i64)?;
+                statement.bind(2, channel.0 as i64)?;
+                statement.next()?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// The category a channel is in, if it's a cached guild channel with one.
+pub fn category_of(cache: &Cache, channel: ChannelId) -> Option<ChannelId> {
+    cache
+        .guild_channel(channel)
+        .filter(|channel| channel.kind != ChannelType::Category)
+        .and_then(|channel| channel.parent_id)
+}
+
+impl TypeMapKey for Settings {
+    type Value = Arc<RwLock<Settings>>;
+}
+
+/// The settings of the running bot.
+pub async fn get(ctx: &Context) -> Arc<RwLock<Settings>> {
+    let data = ctx.data.read().await;
+    data.get::<Settings>()
+        .expect("Expected Settings in TypeMap.")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A migrated database with rows from before settings were kept per guild.
+    fn settings() -> Settings {
+        let connection = sqlite::open(":memory:").unwrap();
+        migrations::migrate(&connection).unwrap();
+        connection
+            .execute(
+                "INSERT INTO banned_channels (channel_id) VALUES (1), (2), (3);
+                INSERT INTO enforced_categories (category_id) VALUES (4);",
+            )
+            .unwrap();
+        Settings {
+            connection: Mutex::new(connection),
+            guilds: HashMap::new(),
+        }
+    }
+
+    fn rows(settings: &Settings, table: &str, column: &str) -> Vec<(i64, Option<i64>)> {
+        let conn_lock = lock(&settings.connection);
+        let mut cursor = conn_lock
+            .prepare(format!(
+                "SELECT {}, guild_id FROM {} ORDER BY 1",
+                column, table
+            ))
+            .unwrap()
+            .into_cursor();
+        let mut rows = Vec::new();
+        while let Some(row) = cursor.next().unwrap() {
+            rows.push((row[0].as_integer().unwrap(), row[1].as_integer()));
+        }
+        rows
+    }
+
+    #[test]
+    fn loading_a_guild_claims_its_channels() {
+        let mut settings = settings();
+        settings
+            .load_guild(GuildId(10), &[ChannelId(1), ChannelId(4)])
+            .unwrap();
+
+        assert_eq!(
+            rows(&settings, "banned_channels", "channel_id"),
+            [(1, Some(10)), (2, None), (3, None)]
+        );
+        assert_eq!(
+            rows(&settings, "enforced_categories", "category_id"),
+            [(4, Some(10))]
+        );
+        assert!(settings.is_channel_toggled(GuildId(10), ChannelId(1)));
+        assert!(settings.is_category_enforced(GuildId(10), ChannelId(4)));
+    }
+
+    #[test]
+    fn unclaimed_rows_are_deleted() {
+        let mut settings = settings();
+        settings.load_guild(GuildId(10), &[ChannelId(1)]).unwrap();
+
+        // Guild 20 is still loading, its channel is claimed instead of deleted
+        let deleted = settings
+            .delete_unclaimed(&[
+                (GuildId(10), vec![ChannelId(1)]),
+                (GuildId(20), vec![ChannelId(2)]),
+            ])
+            .unwrap();
+
+        assert_eq!(deleted, 2);
+        assert_eq!(
+            rows(&settings, "banned_channels", "channel_id"),
+            [(1, Some(10)), (2, Some(20))]
+        );
+        assert!(rows(&settings, "enforced_categories", "category_id").is_empty());
+        assert_eq!(settings.delete_unclaimed(&[]).unwrap(), 0);
+    }
+}
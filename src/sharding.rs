@@ -0,0 +1,140 @@
+use serenity::client::Client;
+use serenity::prelude::TypeMapKey;
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Which shards this process runs, from `SHARD_COUNT` with `SHARD_ID` or `SHARD_RANGE`.
+///
+/// Without any of them Discord's recommended number of shards is used, all in this process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sharding {
+    Auto,
+    /// Every shard out of a fixed number
+    All {
+        total: u64,
+    },
+    /// Shards `first` up to and including `last`, out of `total`
+    Range {
+        first: u64,
+        last: u64,
+        total: u64,
+    },
+}
+
+impl Sharding {
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name| env::var(name).ok().filter(|value| !value.trim().is_empty());
+        let (count, id, range) = (var("SHARD_COUNT"), var("SHARD_ID"), var("SHARD_RANGE"));
+
+        let total = match count {
+            Some(count) => match count.trim().parse() {
+                Ok(total) if total > 0 => total,
+                _ => return Err(format!("SHARD_COUNT is not a number of shards: {}", count)),
+            },
+            None if id.is_some() || range.is_some() => {
+                return Err("SHARD_ID and SHARD_RANGE need SHARD_COUNT".to_string())
+            }
+            None => return Ok(Sharding::Auto),
+        };
+
+        let (first, last) = match (id, range) {
+            (Some(_), Some(_)) => return Err("Set SHARD_ID or SHARD_RANGE, not both".to_string()),
+            (Some(id), None) => {
+                let id = id
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("SHARD_ID is not a shard id: {}", id))?;
+                (id, id)
+            }
+            (None, Some(range)) => {
+                let parsed = range.split_once('-').and_then(|(first, last)| {
+                    Some((first.trim().parse().ok()?, last.trim().parse().ok()?))
+                });
+                match parsed {
+                    Some((first, last)) if first <= last => (first, last),
+                    _ => return Err(format!("SHARD_RANGE is not a range like 0-3: {}", range)),
+                }
+            }
+            (None, None) => return Ok(Sharding::All { total }),
+        };
+
+        if last >= total {
+            return Err(format!(
+                "Shard {} doesn't exist with SHARD_COUNT {}, ids start at 0",
+                last, total
+            ));
+        }
+        Ok(Sharding::Range { first, last, total })
+    }
+
+    /// Whether this process runs every shard, so that no other process has guilds of its own.
+    pub fn runs_every_shard(self) -> bool {
+        match self {
+            Sharding::Auto | Sharding::All { .. } => true,
+            Sharding::Range { first, last, total } => first == 0 && last + 1 == total,
+        }
+    }
+
+    /// Connect the shards and handle their events until the client stops.
+    pub async fn start(self, client: &mut Client) -> serenity::Result<()> {
+        match self {
+            Sharding::Auto => client.start_autosharded().await,
+            Sharding::All { total } => client.start_shards(total).await,
+            Sharding::Range { first, last, total } => {
+                client.start_shard_range([first, last], total).await
+            }
+        }
+    }
+}
+
+impl fmt::Display for Sharding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sharding::Auto => write!(f, "automatic"),
+            Sharding::All { total } => write!(f, "all {} shards", total),
+            Sharding::Range { first, last, total } if first == last => {
+                write!(f, "shard {} of {}", first, total)
+            }
+            Sharding::Range { first, last, total } => {
+                write!(f, "shards {} to {} of {}", first, last, total)
+            }
+        }
+    }
+}
+
+/// Shards that loaded all of their guilds since the process started.
+pub struct LoadedShards {
+    /// `None` once every shard loaded, or when other processes run some of the shards
+    shards: Mutex<Option<HashSet<u64>>>,
+}
+
+impl TypeMapKey for LoadedShards {
+    type Value = Arc<LoadedShards>;
+}
+
+impl LoadedShards {
+    pub fn new(sharding: Sharding) -> Self {
+        LoadedShards {
+            shards: Mutex::new(sharding.runs_every_shard().then(HashSet::new)),
+        }
+    }
+
+    /// Record that a shard loaded its guilds. Returns true the first time every one of `total`
+    /// shards did, when every guild the bot is in is known.
+    pub fn loaded(&self, shard: u64, total: u64) -> bool {
+        let mut shards = self.shards.lock().unwrap_or_else(PoisonError::into_inner);
+        let loaded = match shards.as_mut() {
+            Some(loaded) => loaded,
+            None => return false,
+        };
+        loaded.insert(shard);
+        if loaded.len() as u64 >= total {
+            *shards = None;
+            return true;
+        }
+        false
+    }
+}
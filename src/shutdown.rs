@@ -0,0 +1,94 @@
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::client::Context;
+use serenity::prelude::{Mutex, TypeMapKey};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long shutting down waits for messages that are still being enforced
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the running tasks are counted while waiting for them
+const DRAIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shut the shards down on the first SIGTERM or SIGINT, which makes the client return. A second
+/// signal exits right away, for when shutting down hangs.
+pub async fn stop_on_signal(shard_manager: Arc<Mutex<ShardManager>>) {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(why) => {
+            error!(error = %why, "Could not wait for signals");
+            return;
+        }
+    };
+
+    let signal = tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    };
+    info!(signal, "Shutting down");
+    tokio::spawn(async move {
+        shard_manager.lock().await.shutdown_all().await;
+    });
+
+    // Exit codes like a shell reports for a process killed by the signal
+    let (signal, code) = tokio::select! {
+        _ = terminate.recv() => ("SIGTERM", 143),
+        _ = tokio::signal::ctrl_c() => ("SIGINT", 130),
+    };
+    warn!(signal, "Exiting without finishing the shutdown");
+    process::exit(code);
+}
+
+/// Messages being enforced, which can still post a notice that has to be queued for removal.
+#[derive(Default)]
+pub struct Tasks {
+    running: AtomicUsize,
+}
+
+impl TypeMapKey for Tasks {
+    type Value = Arc<Tasks>;
+}
+
+impl Tasks {
+    /// Count a task as running until the returned guard is dropped.
+    pub async fn start(ctx: &Context) -> TaskGuard {
+        let tasks = {
+            let data = ctx.data.read().await;
+            data.get::<Tasks>()
+                .expect("Expected Tasks in TypeMap.")
+                .clone()
+        };
+        tasks.running.fetch_add(1, Ordering::SeqCst);
+        TaskGuard(tasks)
+    }
+
+    /// Wait until no task is running, or give up after [`DRAIN_TIMEOUT`].
+    pub async fn wait(&self) {
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        loop {
+            let running = self.running.load(Ordering::SeqCst);
+            if running == 0 {
+                return;
+            }
+            if Instant::now() >= deadline {
+                warn!(running, "Gave up waiting for messages being enforced");
+                return;
+            }
+            tokio::time::sleep(DRAIN_INTERVAL).await;
+        }
+    }
+}
+
+/// A running task, see [`Tasks::start`].
+pub struct TaskGuard(Arc<Tasks>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,500 @@
+use serenity::builder::CreateApplicationCommand;
+use serenity::client::Context;
+use serenity::json::Value;
+use serenity::model::channel::ChannelType;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOption as CommandOption,
+    ApplicationCommandInteractionDataOptionValue as OptionValue, ApplicationCommandOptionType,
+};
+use serenity::model::interactions::autocomplete::AutocompleteInteraction;
+use serenity::model::interactions::InteractionResponseType;
+use serenity::model::Permissions;
+use tracing::error;
+
+use crate::commands::{
+    attachments_summary, caption_summary, domains_summary, enforce_category_response,
+    enforce_response, policy_summary,
+};
+use crate::error::{Error, Result};
+use crate::metrics;
+use crate::policy::{parse_domain, AttachmentType, Rule, ThreadMode};
+use crate::settings::{self, category_of, Scope};
+use crate::status;
+
+/// Channel kinds that can be enforced
+const CHANNEL_TYPES: [ChannelType; 7] = [
+    ChannelType::Text,
+    ChannelType::News,
+    ChannelType::Forum,
+    ChannelType::Category,
+    ChannelType::PublicThread,
+    ChannelType::PrivateThread,
+    ChannelType::NewsThread,
+];
+
+/// Suggested attachment types for autocomplete
+const ATTACHMENT_PRESETS: [&str; 8] = [
+    "image/*",
+    "video/*",
+    "audio/*",
+    "image/gif",
+    ".png",
+    ".jpg",
+    ".gif",
+    ".mp4",
+];
+
+/// Build the `/shut` command and its subcommands.
+pub fn build_command(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    // Hide the command from members without the permission, the handler checks it again
+    command.0.insert(
+        "default_member_permissions",
+        Value::from(Permissions::MANAGE_MESSAGES.bits().to_string()),
+    );
+    command.0.insert("dm_permission", Value::from(false));
+
+    command
+        .name("shut")
+        .description("Configure which messages SHUT removes")
+        .create_option(|sub| {
+            sub.name("toggle")
+                .description("Start or stop removing non-media messages from a channel or category")
+                .kind(ApplicationCommandOptionType::SubCommand)
+                .create_sub_option(channel_option)
+                .create_sub_option(|option| {
+                    option
+                        .name("enabled")
+                        .description("Whether to enforce the channel, toggles it if left out")
+                        .kind(ApplicationCommandOptionType::Boolean)
+                })
+        })
+        .create_option(|sub| {
+            sub.name("status")
+                .description("Show a channel's policy")
+                .kind(ApplicationCommandOptionType::SubCommand)
+                .create_sub_option(channel_option)
+        })
+        .create_option(|group| {
+            group
+                .name("config")
+                .description("Change a channel's policy")
+                .kind(ApplicationCommandOptionType::SubCommandGroup)
+                .create_sub_option(|sub| {
+                    sub.name("policy")
+                        .description("Choose whether a kind of content counts as media")
+                        .kind(ApplicationCommandOptionType::SubCommand)
+                        .create_sub_option(|option| {
+                            option
+                                .name("rule")
+                                .description("The rule to change")
+                                .kind(ApplicationCommandOptionType::String)
+                                .required(true);
+                            for rule in Rule::ALL {
+                                option.add_string_choice(rule.name(), rule.name());
+                            }
+                            option
+                        })
+                        .create_sub_option(|option| {
+                            option
+                                .name("enabled")
+                                .description("Whether the rule is on")
+                                .kind(ApplicationCommandOptionType::Boolean)
+                                .required(true)
+                        })
+                        .create_sub_option(channel_option)
+                })
+                .create_sub_option(|sub| {
+                    sub.name("threads")
+                        .description("Choose how the policy applies to threads and forum posts")
+                        .kind(ApplicationCommandOptionType::SubCommand)
+                        .create_sub_option(|option| {
+                            option
+                                .name("mode")
+                                .description("Check every message, none, or only the first one")
+                                .kind(ApplicationCommandOptionType::String)
+                                .required(true);
+                            for mode in ThreadMode::ALL {
+                                option.add_string_choice(mode.name(), mode.name());
+                            }
+                            option
+                        })
+                        .create_sub_option(channel_option)
+                })
+                .create_sub_option(|sub| {
+                    sub.name("caption")
+                        .description("Set the longest caption allowed next to media")
+                        .kind(ApplicationCommandOptionType::SubCommand)
+                        .create_sub_option(|option| {
+                            option
+                                .name("limit")
+                                .description(
+                                    "Characters of text, not counting links, 0 for no limit",
+                                )
+                                .kind(ApplicationCommandOptionType::Integer)
+                                .min_int_value(0)
+                                .required(true)
+                        })
+                        .create_sub_option(channel_option)
+                })
+                .create_sub_option(|sub| {
+                    sub.name("attachments")
+                        .description("Limit which attachments count as media")
+                        .kind(ApplicationCommandOptionType::SubCommand)
+                        .create_sub_option(|option| {
+                            option
+                                .name("action")
+                                .description("What to do with the type")
+                                .kind(ApplicationCommandOptionType::String)
+                                .add_string_choice("add", "add")
+                                .add_string_choice("remove", "remove")
+                                .add_string_choice("clear", "clear")
+                                .required(true)
+                        })
+                        .create_sub_option(|option| {
+                            option
+                                .name("type")
+                                .description("A MIME type like image/* or an extension like .png")
+                                .kind(ApplicationCommandOptionType::String)
+                                .set_autocomplete(true)
+                        })
+                        .create_sub_option(channel_option)
+                })
+                .create_sub_option(|sub| {
+                    sub.name("domains")
+                        .description("Choose which linked domains count as media")
+                        .kind(ApplicationCommandOptionType::SubCommand)
+                        .create_sub_option(|option| {
+                            option
+                                .name("action")
+                                .description("What to do with the domain")
+                                .kind(ApplicationCommandOptionType::String)
+                                .add_string_choice("allow", "allow")
+                                .add_string_choice("deny", "deny")
+                                .add_string_choice("remove", "remove")
+                                .required(true)
+                        })
+                        .create_sub_option(|option| {
+                            option
+                                .name("domain")
+                                .description("A domain like youtube.com")
+                                .kind(ApplicationCommandOptionType::String)
+                                .set_autocomplete(true)
+                                .required(true)
+                        })
+                        .create_sub_option(|option| {
+                            option
+                                .name("server")
+                                .description(
+                                    "Change the server-wide lists instead of the channel's",
+                                )
+                                .kind(ApplicationCommandOptionType::Boolean)
+                        })
+                        .create_sub_option(channel_option)
+                })
+        })
+}
+
+fn channel_option(
+    option: &mut serenity::builder::CreateApplicationCommandOption,
+) -> &mut serenity::builder::CreateApplicationCommandOption {
+    option
+        .name("channel")
+        .description("The channel, defaults to the current one")
+        .kind(ApplicationCommandOptionType::Channel)
+        .channel_types(&CHANNEL_TYPES)
+}
+
+/// Register `/shut` in the listed guilds, or globally if there are none.
+///
+/// Guild commands update instantly, which is handy while testing; global ones can take an hour.
+pub async fn register(ctx: &Context, guilds: &[GuildId]) -> serenity::Result<()> {
+    use serenity::model::interactions::application_command::ApplicationCommand;
+
+    if guilds.is_empty() {
+        ApplicationCommand::set_global_application_commands(&ctx.http, |commands| {
+            commands.create_application_command(build_command)
+        })
+        .await?;
+    } else {
+        for guild in guilds {
+            guild
+                .set_application_commands(&ctx.http, |commands| {
+                    commands.create_application_command(build_command)
+                })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Find a resolved option by name.
+fn option<'a>(options: &'a [CommandOption], name: &str) -> Option<&'a OptionValue> {
+    options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.resolved.as_ref())
+}
+
+fn string_option<'a>(options: &'a [CommandOption], name: &str) -> Option<&'a str> {
+    match option(options, name) {
+        Some(OptionValue::String(value)) => Some(value),
+        _ => None,
+    }
+}
+
+/// The subcommand name and its options, flattening the `config` group.
+fn subcommand(options: &[CommandOption]) -> Option<(&str, &[CommandOption])> {
+    let top = options.first()?;
+    if top.kind == ApplicationCommandOptionType::SubCommandGroup {
+        let sub = top.options.first()?;
+        Some((&sub.name, &sub.options))
+    } else {
+        Some((&top.name, &top.options))
+    }
+}
+
+pub async fn handle_command(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> serenity::Result<()> {
+    let response = match run_command(ctx, command).await {
+        Ok(response) => response,
+        Err(why) => {
+            if let Error::Discord(_) = why {
+                metrics::get(ctx).await.discord_error();
+            }
+            error!(
+                guild = ?command.guild_id,
+                channel = %command.channel_id,
+                user = %command.user.id,
+                command = %command.data.name,
+                error = %why,
+                "Could not run a slash command"
+            );
+            why.user_message().to_string()
+        }
+    };
+
+    command
+        .create_interaction_response(&ctx.http, |response_builder| {
+            response_builder
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| data.content(response).ephemeral(true))
+        })
+        .await
+}
+
+/// Run a `/shut` command and return the reply.
+async fn run_command(ctx: &Context, command: &ApplicationCommandInteraction) -> Result<String> {
+    let guild_id = match command.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok("SHUT only works in servers".to_string()),
+    };
+
+    // Same gate as the prefix commands
+    let allowed = matches!(
+        command.member.as_ref().and_then(|member| member.permissions),
+        Some(permissions) if permissions.manage_messages()
+    );
+    if !allowed {
+        return Ok("You need the Manage Messages permission to configure SHUT".to_string());
+    }
+
+    let (name, options) = match subcommand(&command.data.options) {
+        Some(subcommand) => subcommand,
+        None => return Ok("Unknown command".to_string()),
+    };
+    let (channel, is_category) = match option(options, "channel") {
+        Some(OptionValue::Channel(channel)) => (channel.id, channel.kind == ChannelType::Category),
+        _ => (command.channel_id, false),
+    };
+    let category = category_of(&ctx.cache, channel);
+
+    let settings_lock = settings::get(ctx).await;
+    let mut settings = settings_lock.write().await;
+
+    let response = match name {
+        "toggle" if is_category => {
+            let enforced = match option(options, "enabled") {
+                Some(OptionValue::Boolean(enabled)) => *enabled,
+                _ => !settings.is_category_enforced(guild_id, channel),
+            };
+            let was_enforced = settings.set_category_enforced(guild_id, channel, enforced)?;
+            enforce_category_response(channel, was_enforced, enforced)
+        }
+        "toggle" => {
+            let enforced = match option(options, "enabled") {
+                Some(OptionValue::Boolean(enabled)) => *enabled,
+                _ => !settings.is_enforced(guild_id, channel, category),
+            };
+            let was_enforced =
+                settings.set_channel_enforced(guild_id, channel, category, enforced)?;
+            enforce_response(channel, was_enforced, enforced)
+        }
+        "status" => format!(
+            "{}\n{}",
+            status::enforcement_reason(&settings, guild_id, channel, category),
+            policy_summary(&settings, guild_id, channel, category)
+        ),
+        "policy" => {
+            let rule = string_option(options, "rule").and_then(|rule| rule.parse::<Rule>().ok());
+            let enabled = match option(options, "enabled") {
+                Some(OptionValue::Boolean(enabled)) => Some(*enabled),
+                _ => None,
+            };
+            if let (Some(rule), Some(enabled)) = (rule, enabled) {
+                let mut policy = settings.policy(guild_id, channel, category);
+                policy.set(rule, enabled);
+                settings.set_policy(guild_id, channel, policy)?;
+            }
+            policy_summary(&settings, guild_id, channel, category)
+        }
+        "threads" => {
+            if let Some(Ok(mode)) = string_option(options, "mode").map(str::parse::<ThreadMode>) {
+                let mut policy = settings.policy(guild_id, channel, category);
+                policy.threads = mode;
+                settings.set_policy(guild_id, channel, policy)?;
+            }
+            policy_summary(&settings, guild_id, channel, category)
+        }
+        "caption" => {
+            if let Some(OptionValue::Integer(limit)) = option(options, "limit") {
+                let mut policy = settings.policy(guild_id, channel, category);
+                policy.max_caption = match *limit {
+                    limit if limit <= 0 => None,
+                    limit => Some(limit.min(u32::MAX as i64) as u32),
+                };
+                settings.set_policy(guild_id, channel, policy)?;
+            }
+            caption_summary(&settings, guild_id, channel, category)
+        }
+        "attachments" => {
+            let action = string_option(options, "action").unwrap_or_default();
+            if action == "clear" {
+                settings.clear_attachment_types(guild_id, channel)?;
+            } else {
+                let ty = match string_option(options, "type").map(str::parse::<AttachmentType>) {
+                    Some(Ok(ty)) => ty,
+                    Some(Err(why)) => return Ok(why),
+                    None => return Ok("Pick an attachment type to add or remove".to_string()),
+                };
+                if action == "add" {
+                    settings.add_attachment_type(guild_id, channel, ty)?;
+                } else {
+                    settings.remove_attachment_type(guild_id, channel, &ty)?;
+                }
+            }
+            attachments_summary(&settings, guild_id, channel, category)
+        }
+        "domains" => {
+            let action = string_option(options, "action").unwrap_or_default();
+            let domain = string_option(options, "domain").unwrap_or_default();
+            let domain = match parse_domain(domain) {
+                Some(domain) => domain,
+                None => return Ok(format!("`{}` is not a valid domain", domain)),
+            };
+            let scope = match option(options, "server") {
+                Some(OptionValue::Boolean(true)) => Scope::Guild(guild_id),
+                _ => Scope::Channel(guild_id, channel),
+            };
+            match action {
+                "allow" | "deny" => {
+                    settings.add_domain(scope, domain, action == "allow")?;
+                }
+                _ => {
+                    settings.remove_domain(scope, &domain)?;
+                }
+            }
+            domains_summary(&settings, guild_id, channel)
+        }
+        _ => "Unknown command".to_string(),
+    };
+    Ok(response)
+}
+
+pub async fn handle_autocomplete(
+    ctx: &Context,
+    autocomplete: &AutocompleteInteraction,
+) -> serenity::Result<()> {
+    let choices = autocomplete_choices(ctx, autocomplete).await;
+
+    autocomplete
+        .create_autocomplete_response(&ctx.http, |response| {
+            for choice in choices {
+                response.add_string_choice(&choice, &choice);
+            }
+            response
+        })
+        .await
+}
+
+/// Suggestions for the option the user is typing in, at most 25 as Discord allows.
+async fn autocomplete_choices(
+    ctx: &Context,
+    autocomplete: &AutocompleteInteraction,
+) -> Vec<String> {
+    let guild_id = match autocomplete.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Vec::new(),
+    };
+    let (_, options) = match subcommand(&autocomplete.data.options) {
+        Some(subcommand) => subcommand,
+        None => return Vec::new(),
+    };
+    let focused = match options.iter().find(|option| option.focused) {
+        Some(focused) => focused,
+        None => return Vec::new(),
+    };
+    let input = focused
+        .value
+        .as_ref()
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    // Options aren't resolved during autocomplete, so read the raw channel id
+    let channel = options
+        .iter()
+        .find(|option| option.name == "channel")
+        .and_then(|option| option.value.as_ref())
+        .and_then(Value::as_str)
+        .and_then(|id| id.parse().ok())
+        .map_or(autocomplete.channel_id, ChannelId);
+
+    let settings_lock = settings::get(ctx).await;
+    let settings = settings_lock.read().await;
+
+    let mut candidates: Vec<String> = match focused.name.as_str() {
+        "type" => settings
+            .attachment_types(guild_id, channel, category_of(&ctx.cache, channel))
+            .iter()
+            .map(ToString::to_string)
+            .chain(ATTACHMENT_PRESETS.iter().map(ToString::to_string))
+            .collect(),
+        "domain" => {
+            let guild = settings.domain_filter(Scope::Guild(guild_id));
+            let channel = settings.domain_filter(Scope::Channel(guild_id, channel));
+            guild
+                .allow
+                .into_iter()
+                .chain(guild.deny)
+                .chain(channel.allow)
+                .chain(channel.deny)
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    if !input.is_empty() {
+        candidates.insert(0, input.clone());
+    }
+
+    let mut choices = Vec::new();
+    for candidate in candidates {
+        if candidate.contains(&input) && !choices.contains(&candidate) {
+            choices.push(candidate);
+        }
+    }
+    choices.truncate(25);
+    choices
+}
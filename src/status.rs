@@ -0,0 +1,255 @@
+use serenity::builder::{CreateComponents, CreateEmbed};
+use serenity::cache::Cache;
+use serenity::client::Context;
+use serenity::model::channel::ChannelType;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::interactions::message_component::{ButtonStyle, MessageComponentInteraction};
+use serenity::model::interactions::InteractionResponseType;
+use serenity::prelude::Mentionable;
+use serenity::utils::Colour;
+
+use crate::policy::{ChannelPolicy, Rule, ThreadMode};
+use crate::settings::{self, Settings};
+
+/// Channels listed on each page of `~shut list`
+const PAGE_SIZE: usize = 10;
+/// Custom id prefix of the list's page buttons, followed by the page to show
+const PAGE_BUTTON: &str = "shut-list:";
+
+/// Why a channel is or isn't enforced, in plain words.
+pub fn enforcement_reason(
+    settings: &Settings,
+    guild: GuildId,
+    channel: ChannelId,
+    category: Option<ChannelId>,
+) -> String {
+    let category_enforced =
+        matches!(category, Some(category) if settings.is_category_enforced(guild, category));
+
+    if settings.is_category_enforced(guild, channel) {
+        format!(
+            "Every channel in {} is enforced, unless it opted out",
+            channel.mention()
+        )
+    } else if settings.is_channel_toggled(guild, channel) {
+        format!("{} was toggled on by itself", channel.mention())
+    } else if let (true, Some(category)) = (category_enforced, category) {
+        if settings.is_enforced(guild, channel, Some(category)) {
+            format!(
+                "{} is enforced because every channel in {} is",
+                channel.mention(),
+                category.mention()
+            )
+        } else {
+            format!(
+                "{} opted out of the enforcement of {}",
+                channel.mention(),
+                category.mention()
+            )
+        }
+    } else {
+        format!(
+            "{} isn't enforced, toggle it with `{}toggle_channel`",
+            channel.mention(),
+            settings.guild_config(guild).prefix
+        )
+    }
+}
+
+/// The policy in a few words, for one line of `~shut list`.
+pub fn policy_brief(policy: &ChannelPolicy) -> String {
+    let rules: Vec<_> = Rule::ALL
+        .into_iter()
+        .filter(|rule| policy.get(*rule))
+        .map(Rule::name)
+        .collect();
+    let mut brief = if rules.is_empty() {
+        "nothing allowed".to_string()
+    } else {
+        rules.join(", ")
+    };
+    if let Some(limit) = policy.max_caption {
+        brief.push_str(&format!(" · caption {}", limit));
+    }
+    if policy.threads != ThreadMode::Enforce {
+        brief.push_str(&format!(" · threads: {}", policy.threads));
+    }
+    brief
+}
+
+/// One line for every enforced category and channel in a guild, in the order Discord shows them.
+pub fn enforced_channels(cache: &Cache, settings: &Settings, guild: GuildId) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    let mut categories: Vec<_> = cache
+        .guild_categories(guild)
+        .map(|categories| {
+            categories
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+        .unwrap_or_default();
+    categories.sort_by_key(|category| (category.position, category.id));
+    for category in categories {
+        if settings.is_category_enforced(guild, category.id) {
+            lines.push(format!(
+                "**{}** (category): {}",
+                category.name,
+                policy_brief(&settings.policy(guild, category.id, None))
+            ));
+        }
+    }
+
+    let mut channels: Vec<_> = cache
+        .guild_channels(guild)
+        .map(|channels| channels.iter().map(|entry| entry.value().clone()).collect())
+        .unwrap_or_default();
+    channels.retain(|channel| channel.kind != ChannelType::Category);
+    channels.sort_by_key(|channel| (channel.position, channel.id));
+    for channel in channels {
+        let category = channel.parent_id;
+        if !settings.is_enforced(guild, channel.id, category) {
+            continue;
+        }
+
+        let mut line = format!(
+            "{}: {}",
+            channel.mention(),
+            policy_brief(&settings.policy(guild, channel.id, category))
+        );
+        if !settings.is_channel_toggled(guild, channel.id) {
+            if let Some(category) = category {
+                line.push_str(&format!(" (through {})", category.mention()));
+            }
+        }
+        lines.push(line);
+    }
+
+    lines
+}
+
+pub fn page_count(lines: &[String]) -> usize {
+    ((lines.len() + PAGE_SIZE - 1) / PAGE_SIZE).max(1)
+}
+
+/// Fill in an embed with one page of [`enforced_channels`].
+///
+/// `prefix` is the guild's command prefix, for the hint when nothing is enforced.
+pub fn list_embed<'a>(
+    embed: &'a mut CreateEmbed,
+    lines: &[String],
+    page: usize,
+    prefix: &str,
+) -> &'a mut CreateEmbed {
+    let description = if lines.is_empty() {
+        format!(
+            "No channels are enforced, toggle one with `{}toggle_channel`",
+            prefix
+        )
+    } else {
+        lines
+            .iter()
+            .skip(page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    embed
+        .title("Enforced channels")
+        .description(description)
+        .footer(|f| {
+            f.text(format!(
+                "Page {} of {} | {} enforced",
+                page + 1,
+                page_count(lines),
+                lines.len()
+            ))
+        })
+        .colour(Colour::BLURPLE)
+}
+
+/// Previous and next buttons for a page of the list, left out when everything fits on one page.
+pub fn list_buttons(
+    components: &mut CreateComponents,
+    page: usize,
+    pages: usize,
+) -> &mut CreateComponents {
+    if pages <= 1 {
+        return components;
+    }
+
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .custom_id(format!("{}{}", PAGE_BUTTON, page.saturating_sub(1)))
+                .label("Previous")
+                .style(ButtonStyle::Secondary)
+                .disabled(page == 0)
+        })
+        .create_button(|button| {
+            button
+                .custom_id(format!("{}{}", PAGE_BUTTON, page + 1))
+                .label("Next")
+                .style(ButtonStyle::Secondary)
+                .disabled(page + 1 >= pages)
+        })
+    })
+}
+
+/// Show another page when a list button is pressed.
+pub async fn handle_page_button(
+    ctx: &Context,
+    component: &MessageComponentInteraction,
+) -> serenity::Result<()> {
+    let page = match component.data.custom_id.strip_prefix(PAGE_BUTTON) {
+        Some(page) => page.parse::<usize>().unwrap_or(0),
+        None => return Ok(()),
+    };
+    let guild_id = match component.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(()),
+    };
+
+    // Same gate as the commands, the list can show channels the member can't see
+    let allowed = matches!(
+        component.member.as_ref().and_then(|member| member.permissions),
+        Some(permissions) if permissions.manage_messages()
+    );
+    if !allowed {
+        return component
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|data| {
+                        data.content("You need the Manage Messages permission to see this list")
+                            .ephemeral(true)
+                    })
+            })
+            .await;
+    }
+
+    let settings_lock = settings::get(ctx).await;
+    let (lines, prefix) = {
+        let settings = settings_lock.read().await;
+        (
+            enforced_channels(&ctx.cache, &settings, guild_id),
+            settings.guild_config(guild_id).prefix,
+        )
+    };
+    let pages = page_count(&lines);
+    let page = page.min(pages - 1);
+
+    component
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|data| {
+                    data.embed(|e| list_embed(e, &lines, page, &prefix))
+                        .components(|c| list_buttons(c, page, pages))
+                })
+        })
+        .await
+}